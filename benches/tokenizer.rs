@@ -0,0 +1,81 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use libfrps_rs::{Serializer, Tokenizer, Value, ValueTreeBuilder};
+
+/// Response with large array of mixed scalars
+fn mixed_scalars(cnt: usize) -> Vec<u8> {
+    let items = (0..cnt as i64)
+        .map(|i| match i % 5 {
+            0 => Value::Int(i * 1000),
+            1 => Value::Bool(i % 2 == 0),
+            2 => Value::Double(i as f64 / 3.0),
+            3 => Value::Null,
+            _ => Value::Str("x".repeat((i % 16) as usize)),
+        })
+        .collect();
+    let value = Value::Array(items);
+
+    let mut buffer = vec![0; cnt * 16 + 64];
+    let mut serializer = Serializer::new();
+    let written = serializer.write_response(&mut buffer, &value).unwrap();
+    buffer.truncate(written);
+    buffer
+}
+
+/// Call with ints, doubles and datetimes only, about 10 KB long
+fn fixed_size_args() -> Vec<u8> {
+    let items = (0..1000i64)
+        .map(|i| match i % 3 {
+            0 => Value::Int(i * 1_000_000),
+            1 => Value::Double(i as f64 / 7.0),
+            _ => Value::datetime_from_unix(1_500_000_000 + i * 3600),
+        })
+        .collect();
+    let value = Value::Array(items);
+
+    let mut buffer = vec![0; 16 * 1024];
+    let mut serializer = Serializer::new();
+    let mut written = serializer.write_call(&mut buffer, "bench").unwrap();
+    serializer.reset();
+    written += serializer
+        .write_value(&mut buffer[written..], &value)
+        .unwrap();
+    buffer.truncate(written);
+    buffer
+}
+
+fn tokenize_mixed_scalars(c: &mut Criterion) {
+    let data = mixed_scalars(100_000);
+
+    let mut group = c.benchmark_group("tokenizer");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("mixed scalars", |b| {
+        b.iter(|| {
+            let mut tokenizer = Tokenizer::new_frpc();
+            let mut tree = ValueTreeBuilder::new();
+            let res = tokenizer.parse(black_box(&data), &mut tree);
+            assert!(res.is_ok());
+            tree
+        })
+    });
+    group.finish();
+}
+
+fn tokenize_fixed_size_args(c: &mut Criterion) {
+    let data = fixed_size_args();
+
+    let mut group = c.benchmark_group("tokenizer");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("10KB single buffer", |b| {
+        b.iter(|| {
+            let mut tokenizer = Tokenizer::new_frpc();
+            let mut tree = ValueTreeBuilder::new();
+            let res = tokenizer.parse(black_box(&data), &mut tree);
+            assert!(res.is_ok());
+            tree
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, tokenize_mixed_scalars, tokenize_fixed_size_args);
+criterion_main!(benches);
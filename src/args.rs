@@ -0,0 +1,228 @@
+use crate::convert::TypeError;
+use crate::Value;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Error returned when call arguments don't match requested types
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArgsError {
+    /// Call has other number of arguments
+    Count { expected: usize, got: usize },
+    /// Argument at `pos` (counted from 0) has other type
+    Type { pos: usize, error: TypeError },
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgsError::Count { expected, got } => {
+                write!(f, "expected {} arguments, got {}", expected, got)
+            }
+            ArgsError::Type { pos, error } => write!(f, "argument {}: {}", pos, error),
+        }
+    }
+}
+
+impl std::error::Error for ArgsError {}
+
+/// Convert call arguments to tuple of typed values, e.g.
+/// `let (id, name): (i64, String) = FromArgs::from_args(call.values)?;`
+pub trait FromArgs: Sized {
+    fn from_args(args: Vec<Value>) -> Result<Self, ArgsError>;
+}
+
+impl FromArgs for () {
+    fn from_args(args: Vec<Value>) -> Result<Self, ArgsError> {
+        if !args.is_empty() {
+            return Err(ArgsError::Count {
+                expected: 0,
+                got: args.len(),
+            });
+        }
+        Ok(())
+    }
+}
+
+macro_rules! from_args_tuple {
+    ($len:expr => $($t:ident),+) => {
+        impl<$($t),+> FromArgs for ($($t,)+)
+        where
+            $($t: TryFrom<Value, Error = TypeError>),+
+        {
+            fn from_args(args: Vec<Value>) -> Result<Self, ArgsError> {
+                if args.len() != $len {
+                    return Err(ArgsError::Count {
+                        expected: $len,
+                        got: args.len(),
+                    });
+                }
+
+                let mut args = args.into_iter().enumerate();
+                Ok(($({
+                    let (pos, v) = args.next().unwrap();
+                    <$t>::try_from(v).map_err(|error| ArgsError::Type { pos, error })?
+                },)+))
+            }
+        }
+    };
+}
+
+from_args_tuple!(1 => A);
+from_args_tuple!(2 => A, B);
+from_args_tuple!(3 => A, B, C);
+from_args_tuple!(4 => A, B, C, D);
+from_args_tuple!(5 => A, B, C, D, E);
+from_args_tuple!(6 => A, B, C, D, E, F);
+from_args_tuple!(7 => A, B, C, D, E, F, G);
+from_args_tuple!(8 => A, B, C, D, E, F, G, H);
+
+/// Bind call arguments to typed variables by position or return
+/// `ArgsError` of first mismatching argument from enclosing function:
+///
+/// ```
+/// use libfrps_rs::{extract_args, parse_frpc, ArgsError, Value};
+/// use std::collections::HashMap;
+///
+/// fn update(args: Vec<Value>) -> Result<String, ArgsError> {
+///     extract_args!(args => id: i64, name: String, opts: HashMap<String, Value>);
+///     Ok(format!("{} {} {}", id, name, opts.len()))
+/// }
+///
+/// // update(1, "x", {})
+/// let data = hex::decode("ca110300680675706461746508022001785000").unwrap();
+/// let call = parse_frpc(&data).unwrap();
+/// assert_eq!(update(call.values).unwrap(), "1 x 0");
+///
+/// let err = update(vec![Value::Int(1), Value::Int(2), Value::Null]).unwrap_err();
+/// assert_eq!(err.to_string(), "argument 1: expected string, got int");
+/// ```
+#[macro_export]
+macro_rules! extract_args {
+    ($args:expr => $($name:ident: $t:ty),+ $(,)?) => {
+        let ($($name,)+): ($($t,)+) = $crate::FromArgs::from_args($args)?;
+    };
+}
+
+/// Convert tuple of values to call arguments, e.g. `(1, "x", true)`
+pub trait IntoArgs {
+    fn into_args(self) -> Vec<Value>;
+}
+
+impl IntoArgs for () {
+    fn into_args(self) -> Vec<Value> {
+        vec![]
+    }
+}
+
+impl IntoArgs for Vec<Value> {
+    fn into_args(self) -> Vec<Value> {
+        self
+    }
+}
+
+macro_rules! into_args_tuple {
+    ($($t:ident $v:ident),+) => {
+        impl<$($t),+> IntoArgs for ($($t,)+)
+        where
+            $($t: Into<Value>),+
+        {
+            fn into_args(self) -> Vec<Value> {
+                let ($($v,)+) = self;
+                vec![$($v.into()),+]
+            }
+        }
+    };
+}
+
+into_args_tuple!(A a);
+into_args_tuple!(A a, B b);
+into_args_tuple!(A a, B b, C c);
+into_args_tuple!(A a, B b, C c, D d);
+into_args_tuple!(A a, B b, C c, D d, E e);
+into_args_tuple!(A a, B b, C c, D d, E e, F f);
+into_args_tuple!(A a, B b, C c, D d, E e, F f, G g);
+into_args_tuple!(A a, B b, C c, D d, E e, F f, G g, H h);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_frpc, Serializer};
+
+    #[test]
+    fn call_args_to_tuple() {
+        // m(1, "x", true)
+        let data = hex::decode("ca11030068016d080220017811").unwrap();
+        let call = parse_frpc(&data).unwrap();
+
+        let (id, name, flag): (i64, String, bool) = FromArgs::from_args(call.values).unwrap();
+        assert_eq!((id, name.as_str(), flag), (1, "x", true));
+    }
+
+    #[test]
+    fn call_args_type_mismatch() {
+        // m(1, "x", 2)
+        let data = hex::decode("ca11030068016d08022001780804").unwrap();
+        let call = parse_frpc(&data).unwrap();
+
+        let res: Result<(i64, String, bool), _> = FromArgs::from_args(call.values);
+        let err = res.unwrap_err();
+        assert_eq!(
+            err,
+            ArgsError::Type {
+                pos: 2,
+                error: TypeError {
+                    expected: "bool",
+                    got: "int"
+                }
+            }
+        );
+        assert_eq!(err.to_string(), "argument 2: expected bool, got int");
+
+        let res: Result<(i64, String), _> = FromArgs::from_args(vec![Value::Int(1)]);
+        assert_eq!(
+            res.unwrap_err(),
+            ArgsError::Count {
+                expected: 2,
+                got: 1
+            }
+        );
+    }
+
+    #[test]
+    fn call_from_tuple() {
+        let mut buffer = [0u8; 64];
+        let mut serializer = Serializer::new();
+        let cnt = serializer.call(&mut buffer, "m", (1, "x", true)).unwrap();
+        let call = parse_frpc(&buffer[..cnt]).unwrap();
+        assert_eq!(call.to_string(), "m(1, \"x\", true)");
+
+        let (id, name, flag): (i64, String, bool) = FromArgs::from_args(call.values).unwrap();
+        assert_eq!((id, name.as_str(), flag), (1, "x", true));
+
+        assert_eq!(
+            serializer.call(&mut buffer[..cnt - 1], "m", (1, "x", true)),
+            Err("not enought space")
+        );
+    }
+
+    #[test]
+    fn call_with_args_in_chunks() {
+        let args = (-1, 1_000_000, 2.5, false).into_args();
+        let mut data = vec![];
+        let mut buffer = [0u8; 3];
+        let mut serializer = Serializer::new();
+        loop {
+            let cnt = serializer
+                .write_call_with_args(&mut buffer, "method", &args)
+                .unwrap();
+            data.extend_from_slice(&buffer[..cnt]);
+            // serializer stops only when dst is full
+            if cnt < buffer.len() {
+                break;
+            }
+        }
+
+        let call = parse_frpc(&data).unwrap();
+        assert_eq!(call.to_string(), "method(-1, 1000000, 2.5, false)");
+    }
+}
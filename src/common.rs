@@ -175,25 +175,51 @@ Value 	Encoded Value
 */
 
 // Binary format type's ids
+/// Mask of type id in first byte of value
 pub const TYPE_MASK: u8 = 0b1111_1000;
+/// Mask of additional type info (usually number of length octets - 1)
 pub const OCTET_CNT_MASK: u8 = 0b0000_0111;
-pub const U_VINT_ID: u8 = 0b0011_1000; // ver 2.1
-pub const VINT_ID: u8 = 0b0100_0000; // ver 2.1
+/// Positive integer, protocol version 2.x
+pub const U_VINT_ID: u8 = 0b0011_1000;
+/// Negative integer, protocol version 2.x
+pub const VINT_ID: u8 = 0b0100_0000;
 pub const STRING_ID: u8 = 0b0010_0000;
 pub const BIN_ID: u8 = 0b0011_0000;
+/// Null, protocol version 2.0 and newer
 pub const NULL_ID: u8 = 0b0110_0000;
 pub const STRUCT_ID: u8 = 0b0101_0000;
 pub const ARRAY_ID: u8 = 0b0101_1000;
-pub const INT_ID: u8 = 0b0000_1000; // 1.0 & 3.0
+/// Integer, protocol version 1.0 and 3.0 (zigzag encoded)
+pub const INT_ID: u8 = 0b0000_1000;
 pub const BOOL_ID: u8 = 0b0001_0000;
 pub const DOUBLE_ID: u8 = 0b0001_1000;
 pub const DATETIME_ID: u8 = 0b0010_1000;
+/// Method call message type
 pub const CALL_ID: u8 = 0b0110_1000;
+/// Response message type
 pub const RESPOSE_ID: u8 = 0b0111_0000;
+/// Fault response message type
 pub const FAULT_RESPOSE_ID: u8 = 0b0111_1000;
+/// Stream data, FRPS only
 pub const FRPS_DATA_ID: u8 = 0b0000_0000;
 
-pub const MAX_STR_LENGTH: usize = 1024 * 1024 * 1024; // 1 GB
-pub const MAX_BIN_LENGTH: usize = 1024 * 1024 * 1024; // 1 GB
-pub const MAX_ARRAY_LENGTH: usize = 1024 * 1024; // 1 mil members
-pub const MAX_STRUCT_LENGTH: usize = 1024 * 1024; // 1 mil members
+/// Length of FRPS data size field for additional type info of data tag,
+/// `None` for values without meaning
+pub const fn data_len_octets(octets_mask: u8) -> Option<usize> {
+    match octets_mask & OCTET_CNT_MASK {
+        0 => Some(0),
+        1 => Some(2),
+        2 => Some(4),
+        4 => Some(8),
+        _ => None,
+    }
+}
+
+/// Maximal string length accepted by decoder (1 GB)
+pub const MAX_STR_LENGTH: usize = 1024 * 1024 * 1024;
+/// Maximal binary length accepted by decoder (1 GB)
+pub const MAX_BIN_LENGTH: usize = 1024 * 1024 * 1024;
+/// Maximal number of array items accepted by decoder
+pub const MAX_ARRAY_LENGTH: usize = 1024 * 1024;
+/// Maximal number of struct members accepted by decoder
+pub const MAX_STRUCT_LENGTH: usize = 1024 * 1024;
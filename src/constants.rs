@@ -0,0 +1,17 @@
+//! Type ids and limits of FastRPC binary format, useful for tools reading
+//! raw tags or composing messages with `encoding` functions.
+//!
+//! ```
+//! use libfrps_rs::constants::{OCTET_CNT_MASK, STRING_ID, TYPE_MASK};
+//!
+//! // string with 1 octet of length
+//! let tag = 0x20;
+//! assert_eq!(tag & TYPE_MASK, STRING_ID);
+//! assert_eq!(tag & OCTET_CNT_MASK, 0);
+//! ```
+
+pub use crate::common::{
+    ARRAY_ID, BIN_ID, BOOL_ID, CALL_ID, DATETIME_ID, DOUBLE_ID, FAULT_RESPOSE_ID, FRPS_DATA_ID,
+    INT_ID, MAX_ARRAY_LENGTH, MAX_BIN_LENGTH, MAX_STRUCT_LENGTH, MAX_STR_LENGTH, NULL_ID,
+    OCTET_CNT_MASK, RESPOSE_ID, STRING_ID, STRUCT_ID, TYPE_MASK, U_VINT_ID, VINT_ID,
+};
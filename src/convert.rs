@@ -0,0 +1,290 @@
+use crate::datetime::DateTimeVer30;
+use crate::Value;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Error returned when `Value` has other type than requested
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeError {
+    /// Requested type (see `Value::type_name`)
+    pub expected: &'static str,
+    /// Type of value
+    pub got: &'static str,
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "expected {}, got {}", self.expected, self.got)
+    }
+}
+
+impl std::error::Error for TypeError {}
+
+macro_rules! try_from_value {
+    ($t:ty, $variant:ident, $name:expr) => {
+        impl TryFrom<Value> for $t {
+            type Error = TypeError;
+
+            fn try_from(v: Value) -> Result<Self, Self::Error> {
+                match v {
+                    Value::$variant(x) => Ok(x),
+                    v => Err(TypeError {
+                        expected: $name,
+                        got: v.type_name(),
+                    }),
+                }
+            }
+        }
+    };
+}
+
+try_from_value!(i64, Int, "int");
+try_from_value!(String, Str, "string");
+try_from_value!(DateTimeVer30, DateTime, "datetime");
+try_from_value!(HashMap<String, Value>, Struct, "struct");
+try_from_value!(Vec<Value>, Array, "array");
+try_from_value!(f64, Double, "double");
+try_from_value!(bool, Bool, "bool");
+try_from_value!(Vec<u8>, Binary, "binary");
+
+// conversion of borrowed value, `$conv` maps matched variant content
+macro_rules! try_from_value_ref {
+    ($t:ty, $variant:ident, $name:expr, |$x:ident| $conv:expr) => {
+        impl<'a> TryFrom<&'a Value> for $t {
+            type Error = TypeError;
+
+            fn try_from(v: &'a Value) -> Result<Self, Self::Error> {
+                match v {
+                    Value::$variant($x) => Ok($conv),
+                    v => Err(TypeError {
+                        expected: $name,
+                        got: v.type_name(),
+                    }),
+                }
+            }
+        }
+    };
+}
+
+try_from_value_ref!(i64, Int, "int", |x| *x);
+try_from_value_ref!(&'a str, Str, "string", |x| x.as_str());
+try_from_value_ref!(DateTimeVer30, DateTime, "datetime", |x| *x);
+try_from_value_ref!(&'a HashMap<String, Value>, Struct, "struct", |x| x);
+try_from_value_ref!(&'a [Value], Array, "array", |x| x.as_slice());
+try_from_value_ref!(f64, Double, "double", |x| *x);
+try_from_value_ref!(bool, Bool, "bool", |x| *x);
+try_from_value_ref!(&'a [u8], Binary, "binary", |x| x.as_slice());
+
+impl Value {
+    /// Convert struct with members of the same type to map, `None` when
+    /// value is not struct or any member can't be converted
+    pub fn as_map_of<'a, T: TryFrom<&'a Value>>(&'a self) -> Option<HashMap<&'a str, T>> {
+        match self {
+            Value::Struct(v) => v
+                .iter()
+                .map(|(k, x)| T::try_from(x).ok().map(|x| (k.as_str(), x)))
+                .collect(),
+            _ => None,
+        }
+    }
+
+    // struct member `key` converted to `T`, `None` when value is not struct,
+    // member is missing or has other type
+    fn member_as<'a, T: TryFrom<&'a Value>>(&'a self, key: &str) -> Option<T> {
+        match self {
+            Value::Struct(v) => v.get(key).and_then(|x| T::try_from(x).ok()),
+            _ => None,
+        }
+    }
+
+    /// Bool member `key` of struct or `default` when it is missing or has
+    /// other type
+    pub fn get_bool_or(&self, key: &str, default: bool) -> bool {
+        self.member_as(key).unwrap_or(default)
+    }
+
+    /// Int member `key` of struct, see `get_bool_or`
+    pub fn get_i64_or(&self, key: &str, default: i64) -> i64 {
+        self.member_as(key).unwrap_or(default)
+    }
+
+    /// Double member `key` of struct, see `get_bool_or`
+    pub fn get_f64_or(&self, key: &str, default: f64) -> f64 {
+        self.member_as(key).unwrap_or(default)
+    }
+
+    /// String member `key` of struct, see `get_bool_or`
+    pub fn get_str_or<'a>(&'a self, key: &str, default: &'a str) -> &'a str {
+        self.member_as(key).unwrap_or(default)
+    }
+
+    /// Binary value decoded from hexadecimal string
+    pub fn binary_from_hex(s: &str) -> Result<Value, &'static str> {
+        hex::decode(s)
+            .map(Value::Binary)
+            .map_err(|_| "invalid hex string")
+    }
+
+    /// Hexadecimal representation of binary, `None` for other types
+    pub fn binary_to_hex(&self) -> Option<String> {
+        match self {
+            Value::Binary(v) => Some(hex::encode(v)),
+            _ => None,
+        }
+    }
+
+    /// Binary value decoded from standard base64 with padding
+    #[cfg(feature = "base64")]
+    pub fn binary_from_base64(s: &str) -> Result<Value, &'static str> {
+        base64::decode(s)
+            .map(Value::Binary)
+            .map_err(|_| "invalid base64 string")
+    }
+
+    /// Standard base64 representation of binary, `None` for other types
+    #[cfg(feature = "base64")]
+    pub fn binary_to_base64(&self) -> Option<String> {
+        match self {
+            Value::Binary(v) => Some(base64::encode(v)),
+            _ => None,
+        }
+    }
+
+    /// String without allocation for `Str`, other values are formatted
+    pub fn to_cow_str(&self) -> Cow<'_, str> {
+        match self {
+            Value::Str(v) => Cow::Borrowed(v),
+            v => Cow::Owned(v.to_string()),
+        }
+    }
+}
+
+impl<'a> From<&'a Value> for Cow<'a, str> {
+    fn from(v: &'a Value) -> Self {
+        v.to_cow_str()
+    }
+}
+
+macro_rules! value_from {
+    ($t:ty, $variant:ident) => {
+        impl From<$t> for Value {
+            fn from(v: $t) -> Self {
+                Value::$variant(v.into())
+            }
+        }
+    };
+}
+
+value_from!(i64, Int);
+value_from!(i32, Int);
+value_from!(u32, Int);
+value_from!(String, Str);
+value_from!(&str, Str);
+value_from!(DateTimeVer30, DateTime);
+value_from!(HashMap<String, Value>, Struct);
+value_from!(Vec<Value>, Array);
+value_from!(f64, Double);
+value_from!(bool, Bool);
+value_from!(Vec<u8>, Binary);
+value_from!(&[u8], Binary);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn member_or_default() {
+        let mut members = HashMap::new();
+        members.insert("flag".to_owned(), Value::Bool(true));
+        members.insert("id".to_owned(), Value::Int(5));
+        members.insert("ratio".to_owned(), Value::Double(0.5));
+        members.insert("name".to_owned(), Value::Str("x".to_owned()));
+        let v = Value::Struct(members);
+
+        // present with correct type
+        assert!(v.get_bool_or("flag", false));
+        assert_eq!(v.get_i64_or("id", 0), 5);
+        assert_eq!(v.get_f64_or("ratio", 1.0), 0.5);
+        assert_eq!(v.get_str_or("name", "-"), "x");
+
+        // present with other type
+        assert!(!v.get_bool_or("id", false));
+        assert_eq!(v.get_i64_or("flag", 7), 7);
+        assert_eq!(v.get_f64_or("id", 1.0), 1.0);
+        assert_eq!(v.get_str_or("id", "-"), "-");
+
+        // missing member or not struct
+        assert!(v.get_bool_or("missing", true));
+        assert_eq!(v.get_i64_or("missing", 7), 7);
+        assert_eq!(Value::Int(1).get_str_or("name", "-"), "-");
+    }
+
+    #[test]
+    fn try_from_value() {
+        assert_eq!(i64::try_from(Value::Int(5)), Ok(5));
+        assert_eq!(
+            String::try_from(Value::Str("x".to_owned())),
+            Ok("x".to_owned())
+        );
+        assert_eq!(Vec::<u8>::try_from(Value::Binary(vec![1])), Ok(vec![1]));
+
+        let err = bool::try_from(Value::Null).unwrap_err();
+        assert_eq!(err.to_string(), "expected bool, got null");
+    }
+
+    #[test]
+    fn struct_as_map() {
+        let mut members = HashMap::new();
+        members.insert("a".to_owned(), Value::Int(1));
+        members.insert("b".to_owned(), Value::Int(2));
+        let mut v = Value::Struct(members);
+
+        let map = v.as_map_of::<i64>().unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!((map["a"], map["b"]), (1, 2));
+        assert!(v.as_map_of::<&str>().is_none());
+
+        v.as_struct_mut()
+            .unwrap()
+            .insert("c".to_owned(), Value::Str("x".to_owned()));
+        assert!(v.as_map_of::<i64>().is_none());
+        assert!(Value::Int(1).as_map_of::<i64>().is_none());
+    }
+
+    #[test]
+    fn binary_hex() {
+        let v = Value::binary_from_hex("00ff10").unwrap();
+        assert_eq!(v, Value::Binary(vec![0x00, 0xff, 0x10]));
+        assert_eq!(v.binary_to_hex(), Some("00ff10".to_owned()));
+
+        assert_eq!(Value::binary_from_hex("0g"), Err("invalid hex string"));
+        assert_eq!(Value::Str("00".to_owned()).binary_to_hex(), None);
+    }
+
+    #[cfg(feature = "base64")]
+    #[test]
+    fn binary_base64() {
+        let v = Value::Binary(b"frpc\x00".to_vec());
+        let encoded = v.binary_to_base64().unwrap();
+        assert_eq!(encoded, "ZnJwYwA=");
+        assert_eq!(Value::binary_from_base64(&encoded), Ok(v));
+
+        assert_eq!(
+            Value::binary_from_base64("ZnJw*wA="),
+            Err("invalid base64 string")
+        );
+        assert_eq!(Value::Null.binary_to_base64(), None);
+    }
+
+    #[test]
+    fn cow_str() {
+        let v = Value::Str("x".to_owned());
+        assert!(matches!(v.to_cow_str(), Cow::Borrowed("x")));
+
+        let v = Value::Int(5);
+        let s: Cow<str> = (&v).into();
+        assert!(matches!(s, Cow::Owned(ref s) if s == "5"));
+    }
+}
@@ -0,0 +1,187 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Datetime as it is transferred by FastRPC. Unix timestamp is always in
+/// UTC, calendar fields hold local time of `time_zone`. Protocol carries
+/// whole seconds only, see `from_unix_nanos_with_remainder`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTimeVer30 {
+    /// Offset from UTC in quarters of hour (-128..+12)
+    pub time_zone: i8,
+    /// Seconds since unix epoch, -1 when time is not representable and
+    /// calendar fields have to be used
+    pub unix_time: i64,
+    /// Day of week, 0 is Sunday
+    pub week_day: u8,
+    pub sec: u8,
+    pub min: u8,
+    pub hour: u8,
+    pub day: u8,
+    pub month: u8,
+    pub year: u16,
+}
+
+/// Local time of 0000-01-01 00:00:00, lowest time with calendar fields
+const MIN_LOCAL_TIME: i64 = -62_167_219_200;
+/// Local time of 9999-12-31 23:59:59, highest time with calendar fields
+const MAX_LOCAL_TIME: i64 = 253_402_300_799;
+
+impl DateTimeVer30 {
+    /// Create datetime for `unix_time` with calendar fields in local time of
+    /// `time_zone` (in quarters of hour). Calendar fields of time outside of
+    /// years 0-9999 are clamped, `unix_time` is kept as is.
+    pub fn from_unix(unix_time: i64, time_zone: i8) -> DateTimeVer30 {
+        // time::UtcOffset can't hold all protocol zones (down to -32:00) so
+        // we shift timestamp by offset and read calendar fields as UTC
+        let offset = (time_zone as i64) * 15 * 60;
+        let local_time = unix_time
+            .saturating_add(offset)
+            .clamp(MIN_LOCAL_TIME, MAX_LOCAL_TIME);
+        let local = time::OffsetDateTime::from_unix_timestamp(local_time);
+
+        DateTimeVer30 {
+            time_zone,
+            unix_time,
+            week_day: local.weekday().number_days_from_sunday(),
+            sec: local.second(),
+            min: local.minute(),
+            hour: local.hour(),
+            day: local.day(),
+            month: local.month(),
+            year: local.year() as u16,
+        }
+    }
+
+    /// Create datetime for `nanos` since unix epoch, fraction of second is
+    /// dropped (time is rounded down)
+    pub fn from_unix_nanos(nanos: i64, time_zone: i8) -> DateTimeVer30 {
+        DateTimeVer30::from_unix_nanos_with_remainder(nanos, time_zone).0
+    }
+
+    /// Same as `from_unix_nanos` but return also dropped nanoseconds so
+    /// caller can detect precision loss
+    pub fn from_unix_nanos_with_remainder(nanos: i64, time_zone: i8) -> (DateTimeVer30, u32) {
+        let secs = nanos.div_euclid(1_000_000_000);
+        let remainder = nanos.rem_euclid(1_000_000_000) as u32;
+        (DateTimeVer30::from_unix(secs, time_zone), remainder)
+    }
+
+    /// Offset from UTC in seconds
+    pub fn offset_seconds(&self) -> i64 {
+        (self.time_zone as i64) * 15 * 60
+    }
+
+    /// `true` when `unix_time` is -1, i.e. time is not representable by
+    /// timestamp and calendar fields carry the real date
+    pub fn is_epoch_sentinel(&self) -> bool {
+        self.unix_time == -1
+    }
+
+    /// Return unix timestamp (UTC). When `unix_time` is -1 it is computed
+    /// from calendar fields, `None` is returned when they are not valid.
+    pub fn timestamp(&self) -> Option<i64> {
+        if !self.is_epoch_sentinel() {
+            return Some(self.unix_time);
+        }
+
+        let date = time::Date::try_from_ymd(self.year as i32, self.month, self.day).ok()?;
+        let local = date.try_with_hms(self.hour, self.min, self.sec).ok()?;
+        Some(local.assume_utc().timestamp() - self.offset_seconds())
+    }
+}
+
+/// Datetimes are ordered by time, same time in different zones is ordered by
+/// remaining fields
+impl Ord for DateTimeVer30 {
+    fn cmp(&self, other: &DateTimeVer30) -> Ordering {
+        let key = |v: &DateTimeVer30| {
+            (
+                v.timestamp(),
+                v.time_zone,
+                v.unix_time,
+                (v.year, v.month, v.day, v.hour, v.min, v.sec, v.week_day),
+            )
+        };
+        key(self).cmp(&key(other))
+    }
+}
+
+impl PartialOrd for DateTimeVer30 {
+    fn partial_cmp(&self, other: &DateTimeVer30) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for DateTimeVer30 {
+    /// Time is always formatted in UTC
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.timestamp() {
+            Some(ts) => write!(
+                f,
+                "{}",
+                time::OffsetDateTime::from_unix_timestamp(ts).format("%Y-%m-%d %H:%M:%S")
+            ),
+            None => write!(f, "invalid datetime"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negative_zone() {
+        // 2017-01-27 12:39:19 UTC in -2:00
+        let dt = DateTimeVer30::from_unix(1485520759, -8);
+        assert_eq!(dt.offset_seconds(), -2 * 3600);
+        assert_eq!((dt.hour, dt.min, dt.sec), (10, 39, 19));
+        assert_eq!((dt.year, dt.month, dt.day, dt.week_day), (2017, 1, 27, 5));
+        assert_eq!(dt.to_string(), "2017-01-27 12:39:19");
+
+        // lowest zone -32:00 crosses two days
+        let dt = DateTimeVer30::from_unix(1485520759, -128);
+        assert_eq!((dt.month, dt.day, dt.hour), (1, 26, 4));
+
+        // calendar fields are used for unrepresentable time
+        let dt = DateTimeVer30 {
+            unix_time: -1,
+            ..DateTimeVer30::from_unix(1485520759, -8)
+        };
+        assert_eq!(dt.timestamp(), Some(1485520759));
+    }
+
+    #[test]
+    fn nanos_are_truncated() {
+        let (dt, remainder) =
+            DateTimeVer30::from_unix_nanos_with_remainder(1_485_520_759_123_456_789, 0);
+        assert_eq!(dt.unix_time, 1485520759);
+        assert_eq!(remainder, 123_456_789);
+        assert_eq!(
+            dt,
+            DateTimeVer30::from_unix_nanos(1_485_520_759_123_456_789, 0)
+        );
+
+        // before epoch time is rounded down, remainder is never negative
+        let (dt, remainder) = DateTimeVer30::from_unix_nanos_with_remainder(-1_500_000_000, 0);
+        assert_eq!((dt.unix_time, remainder), (-2, 500_000_000));
+        assert_eq!(dt.to_string(), "1969-12-31 23:59:58");
+    }
+
+    #[test]
+    fn out_of_range_is_clamped() {
+        let dt = DateTimeVer30::from_unix(i64::MAX, 48);
+        assert_eq!(dt.unix_time, i64::MAX);
+        assert_eq!((dt.year, dt.month, dt.day), (9999, 12, 31));
+        assert_eq!((dt.hour, dt.min, dt.sec), (23, 59, 59));
+
+        let dt = DateTimeVer30::from_unix(i64::MIN, -128);
+        assert_eq!(dt.unix_time, i64::MIN);
+        assert_eq!((dt.year, dt.month, dt.day), (0, 1, 1));
+        assert_eq!((dt.hour, dt.min, dt.sec), (0, 0, 0));
+
+        // year before 0 doesn't wrap
+        let dt = DateTimeVer30::from_unix(-62_200_000_000, 0);
+        assert_eq!(dt.year, 0);
+    }
+}
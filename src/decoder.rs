@@ -0,0 +1,180 @@
+use crate::tokenizer::{Tokenizer, TokenizerState};
+use crate::value_tree_builder::{ParsedStatus, ValueTreeBuilder};
+use crate::{ParseError, Value};
+use std::task::Poll;
+
+// Input is tokenized in pieces of this size so only values completed in one
+// piece are kept in memory at once
+const CHUNK_SIZE: usize = 4096;
+
+/// Pull decoder returning top-level values (call arguments or response value)
+/// one by one as soon as they are complete.
+#[derive(Debug)]
+pub struct Decoder {
+    tokenizer: Tokenizer,
+    tree: ValueTreeBuilder,
+    consumed: usize,
+    finished: bool,
+}
+
+impl Decoder {
+    /// Decoder of FRPC message
+    pub fn new_frpc() -> Decoder {
+        Decoder::new(Tokenizer::new_frpc())
+    }
+
+    /// Decoder of FRPS message
+    pub fn new_frps() -> Decoder {
+        Decoder::new(Tokenizer::new_frps())
+    }
+
+    /// Use configured `tokenizer`
+    pub fn new(tokenizer: Tokenizer) -> Decoder {
+        Decoder {
+            tokenizer,
+            tree: ValueTreeBuilder::new(),
+            consumed: 0,
+            finished: false,
+        }
+    }
+
+    /// Message type and method name of call
+    pub fn what(&self) -> &ParsedStatus {
+        &self.tree.what
+    }
+
+    /// Framing state of decoded message. Call has no end mark, so it is
+    /// complete when input ends in `TokenizerState::ExpectingOptionalArgs`.
+    pub fn state(&self) -> TokenizerState {
+        self.tokenizer.state()
+    }
+
+    /// Return next complete top-level value. Consumed bytes are removed from
+    /// `input`, `Poll::Pending` means more input is required and
+    /// `Poll::Ready(None)` that message is complete. Fault is returned as two
+    /// values, code and message, with `what()` set to `ParsedStatus::Fault`.
+    /// FRPS fault is complete at end of `input`, optional detail struct
+    /// passed in later input is still returned as third value.
+    pub fn next_value(&mut self, input: &mut &[u8]) -> Result<Poll<Option<Value>>, ParseError> {
+        loop {
+            if !self.tree.values.is_empty() {
+                return Ok(Poll::Ready(Some(self.tree.values.remove(0))));
+            }
+
+            if self.finished {
+                return Ok(Poll::Ready(None));
+            }
+
+            if input.is_empty() {
+                // FRPS fault may be followed by optional detail, without it
+                // fault is complete at end of input
+                if self.tree.what == ParsedStatus::Fault
+                    && self.tokenizer.state() == TokenizerState::ExpectingOptionalArgs
+                {
+                    return Ok(Poll::Ready(None));
+                }
+                return Ok(Poll::Pending);
+            }
+
+            let chunk = &input[..input.len().min(CHUNK_SIZE)];
+            match self.tokenizer.parse(chunk, &mut self.tree) {
+                Ok((_, processed)) => {
+                    self.finished = self.tokenizer.state() == TokenizerState::Complete;
+                    self.consumed += processed;
+                    *input = &input[processed..];
+                }
+                Err(pos) => {
+                    let msg = match &self.tree.what {
+                        ParsedStatus::Error(msg) => msg.clone(),
+                        _ => "invalid data".to_owned(),
+                    };
+                    return Err(ParseError {
+                        pos: self.consumed + pos,
+                        msg,
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pull_call_arguments() {
+        // m(1, 2, 3, 4, "x")
+        let data = hex::decode("ca11030068016d0802080408060808200178").unwrap();
+
+        // all data at once
+        let mut decoder = Decoder::new_frpc();
+        let mut input = &data[..];
+        for i in 1..5 {
+            assert_eq!(
+                decoder.next_value(&mut input),
+                Ok(Poll::Ready(Some(Value::Int(i))))
+            );
+        }
+        assert_eq!(
+            decoder.next_value(&mut input),
+            Ok(Poll::Ready(Some(Value::Str("x".to_owned()))))
+        );
+        assert_eq!(decoder.next_value(&mut input), Ok(Poll::Pending));
+        assert!(input.is_empty());
+        assert_eq!(decoder.state(), TokenizerState::ExpectingOptionalArgs);
+        assert_eq!(decoder.what(), &ParsedStatus::MethodCall("m".to_owned()));
+
+        // byte by byte, every value is returned as soon as it is complete
+        let mut decoder = Decoder::new_frpc();
+        let mut values = vec![];
+        for (i, byte) in data.iter().enumerate() {
+            let mut input = std::slice::from_ref(byte);
+            while let Poll::Ready(Some(v)) = decoder.next_value(&mut input).unwrap() {
+                values.push((i, v));
+            }
+        }
+        let ends: Vec<_> = values.iter().map(|(i, _)| *i).collect();
+        assert_eq!(ends, vec![8, 10, 12, 14, 17]);
+    }
+
+    #[test]
+    fn pull_response() {
+        // response "abc" followed by unrelated data
+        let data = hex::decode("ca110300702003616263ff").unwrap();
+        let mut decoder = Decoder::new_frpc();
+        let mut input = &data[..];
+        assert_eq!(
+            decoder.next_value(&mut input),
+            Ok(Poll::Ready(Some(Value::Str("abc".to_owned()))))
+        );
+        assert_eq!(decoder.next_value(&mut input), Ok(Poll::Ready(None)));
+        assert_eq!(input, &[0xff]);
+    }
+
+    #[test]
+    fn pull_frps_fault() {
+        // FRPS fault(500, "x") without detail
+        let data = hex::decode("ca1102017839f401200178").unwrap();
+        let mut decoder = Decoder::new_frps();
+        let mut values = vec![];
+        for byte in &data {
+            let mut input = std::slice::from_ref(byte);
+            while let Poll::Ready(Some(v)) = decoder.next_value(&mut input).unwrap() {
+                values.push(v);
+            }
+        }
+        assert_eq!(values, vec![Value::Int(500), Value::Str("x".to_owned())]);
+        assert_eq!(decoder.what(), &ParsedStatus::Fault);
+        assert_eq!(decoder.next_value(&mut &[][..]), Ok(Poll::Ready(None)));
+
+        // detail struct {} following in next input
+        let mut input = &[0x50, 0x00][..];
+        assert_eq!(
+            decoder.next_value(&mut input),
+            Ok(Poll::Ready(Some(Value::Struct(Default::default()))))
+        );
+        assert_eq!(decoder.state(), TokenizerState::Complete);
+        assert_eq!(decoder.next_value(&mut input), Ok(Poll::Ready(None)));
+    }
+}
@@ -0,0 +1,198 @@
+//! Low level encodings used by FastRPC protocol
+
+use crate::common::*;
+use crate::serialize::{write_head, write_int};
+
+/** Encodes signed integer as unsigned,
+ * with positive values even and negative values odd
+ * starting around zero.
+ * This saves transfer space and unifies integer encoding.
+ * 0 -> 0
+ * -1 -> 1
+ * 1 -> 2
+ * -2 -> 3
+ * 2 -> 4
+ * ...
+ */
+pub fn zigzag_encode(n: i64) -> u64 {
+    // the right shift has to be arithmetic
+    // negative numbers become all binary 1s
+    // positive numbers become all binary 0s
+    // effectively inverting bits of the result in
+    // case of negative number
+    // let tmp = (n << 1) ^ (n >> 63);
+
+    let tmp = n.overflowing_shl(1).0 ^ n.overflowing_shr(63).0;
+    u64::from_le_bytes(tmp.to_le_bytes())
+}
+
+/// Write tag `type_id` (`STRING_ID`, `BIN_ID`, `STRUCT_ID` or `ARRAY_ID` from
+/// `constants`) followed by `size` in as few octets as possible. Return
+/// number of bytes written.
+pub fn encode_head(type_id: u8, size: usize, dst: &mut [u8]) -> Result<usize, &'static str> {
+    if type_id & OCTET_CNT_MASK != 0 {
+        return Err("invalid type id");
+    }
+    write_head(type_id, size, dst)
+}
+
+/// Write tag and zigzag encoded integer (protocol version 3.0). Return number
+/// of bytes written.
+pub fn encode_int(v: i64, dst: &mut [u8]) -> Result<usize, &'static str> {
+    write_int(v, dst)
+}
+
+/*
+   Decodes signed integer from unsigned,
+   with positive values even and negative values odd
+   starting around zero.
+   This saves transfer space and unifies integer encoding.
+   * -1 -> -9223372036854775808
+   *  0 -> 0
+   *  1 -> -1
+   *  2 -> 1
+   *  3 -> -2
+   *  4 -> 2
+   * ...
+   a = -9223372036854775808 Min i64
+   1000000000000000000000000000000000000000000000000000000000000000
+   a << 1
+   0000000000000000000000000000000000000000000000000000000000000000
+   a >> 63
+   1111111111111111111111111111111111111111111111111111111111111111
+   b = encoded(a)
+   1111111111111111111111111111111111111111111111111111111111111111
+   signed(b) >> 1
+   1111111111111111111111111111111111111111111111111111111111111111
+   unsigned(b) >> 1
+   0111111111111111111111111111111111111111111111111111111111111111
+   -(b & 1))
+   1111111111111111111111111111111111111111111111111111111111111111
+   c = decoded(b)
+   1000000000000000000000000000000000000000000000000000000000000000
+
+   a = -1
+   1111111111111111111111111111111111111111111111111111111111111111
+   a << 1
+   1111111111111111111111111111111111111111111111111111111111111110
+   a >> 63
+   1111111111111111111111111111111111111111111111111111111111111111
+   b = encoded(a)
+   0000000000000000000000000000000000000000000000000000000000000001
+   signed(b) >> 1
+   0000000000000000000000000000000000000000000000000000000000000000
+   unsigned(b) >> 1
+   0000000000000000000000000000000000000000000000000000000000000000
+   -(b & 1))
+   1111111111111111111111111111111111111111111111111111111111111111
+   c = decoded(b)
+   1111111111111111111111111111111111111111111111111111111111111111
+
+   a = 1
+   0000000000000000000000000000000000000000000000000000000000000001
+   a << 1
+   0000000000000000000000000000000000000000000000000000000000000010
+   a >> 63
+   0000000000000000000000000000000000000000000000000000000000000000
+   b = encoded(a)
+   0000000000000000000000000000000000000000000000000000000000000010
+   signed(b) >> 1
+   0000000000000000000000000000000000000000000000000000000000000001
+   unsigned(b) >> 1
+   0000000000000000000000000000000000000000000000000000000000000001
+   -(b & 1))
+   0000000000000000000000000000000000000000000000000000000000000000
+   c = decoded(b)
+   0000000000000000000000000000000000000000000000000000000000000001
+
+   a = 9223372036854775807 - Max i64
+   0111111111111111111111111111111111111111111111111111111111111111
+   a << 1
+   1111111111111111111111111111111111111111111111111111111111111110
+   a >> 63
+   0000000000000000000000000000000000000000000000000000000000000000
+   b = encoded(a)
+   1111111111111111111111111111111111111111111111111111111111111110
+   signed(b) >> 1
+   1111111111111111111111111111111111111111111111111111111111111111
+   unsigned(b) >> 1
+   0111111111111111111111111111111111111111111111111111111111111111
+   -(b & 1))
+   0000000000000000000000000000000000000000000000000000000000000000
+   c = decoded(b)
+   0111111111111111111111111111111111111111111111111111111111111111
+
+   int64_t encode(int64_t n) {
+       return ((n << 1) ^ (n >> 63));
+   }
+   static int64_t decode(int64_t s) {
+       uint64_t n = static_cast<uint64_t>(s);
+       return static_cast<int64_t>((n >> 1) ^ (-(s & 1)));
+   }
+*/
+
+pub fn zigzag_decode(n: u64) -> i64 {
+    let s = i64::from_le_bytes(n.to_le_bytes());
+    i64::from_le_bytes((n >> 1).to_le_bytes()) ^ (-(s & 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_frpc;
+
+    #[test]
+    fn zigzag_table() {
+        let table: [(i64, u64); 7] = [(0, 0), (-1, 1), (1, 2), (-2, 3), (2, 4), (-3, 5), (3, 6)];
+        for (decoded, encoded) in table.iter() {
+            assert_eq!(zigzag_encode(*decoded), *encoded);
+            assert_eq!(zigzag_decode(*encoded), *decoded);
+        }
+
+        assert_eq!(zigzag_encode(i64::MAX), u64::MAX - 1);
+        assert_eq!(zigzag_encode(i64::MIN), u64::MAX);
+    }
+
+    #[test]
+    fn zigzag_roundtrip() {
+        let edges = [i64::MIN, i64::MIN + 1, -1, 0, 1, i64::MAX - 1, i64::MAX];
+        for x in edges.iter() {
+            assert_eq!(zigzag_decode(zigzag_encode(*x)), *x);
+        }
+
+        // walk whole range with prime step plus values around zero
+        let mut x = i64::MIN;
+        while let Some(next) = x.checked_add(0x0001_2345_6789_abcd) {
+            assert_eq!(zigzag_decode(zigzag_encode(x)), x);
+            x = next;
+        }
+        for x in -1000..1000 {
+            assert_eq!(zigzag_decode(zigzag_encode(x)), x);
+        }
+    }
+
+    #[test]
+    fn compose_message() {
+        // m("hello", 300)
+        let mut data = hex::decode("ca11030068016d").unwrap();
+        let mut buffer = [0u8; 9];
+
+        let cnt = encode_head(STRING_ID, 5, &mut buffer).unwrap();
+        assert_eq!(&buffer[..cnt], &[0x20, 0x05]);
+        data.extend_from_slice(&buffer[..cnt]);
+        data.extend_from_slice(b"hello");
+
+        // buffer is just large enough
+        let cnt = encode_int(300, &mut buffer[..3]).unwrap();
+        data.extend_from_slice(&buffer[..cnt]);
+
+        let call = parse_frpc(&data).unwrap();
+        assert_eq!(call.to_string(), "m(\"hello\", 300)");
+
+        assert_eq!(encode_int(300, &mut buffer[..2]), Err("not enought space"));
+        assert_eq!(
+            encode_head(STRING_ID | 1, 5, &mut buffer),
+            Err("invalid type id")
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{parse_frpc, ValueTreeBuilder};
+use byteorder::{BigEndian, ByteOrder};
+use std::io::{self, Read};
+
+/// Default limit of message length, see `FrameReader::max_frame_len`
+const DEFAULT_MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+/// Reads FRPC messages prefixed by 4 byte big endian length, as they are
+/// sent over stream transports like TCP.
+#[derive(Debug)]
+pub struct FrameReader<R: Read> {
+    src: R,
+    buffer: Vec<u8>,
+    max_frame_len: usize,
+}
+
+impl<R: Read> FrameReader<R> {
+    pub fn new(src: R) -> FrameReader<R> {
+        FrameReader {
+            src,
+            buffer: vec![],
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+        }
+    }
+
+    /// Reject messages longer than `len` bytes before memory is reserved
+    /// for them, 16 MiB by default. Length prefix comes from the peer, so
+    /// without limit it could force 4 GiB allocation.
+    pub fn max_frame_len(mut self, len: usize) -> FrameReader<R> {
+        self.max_frame_len = len;
+        self
+    }
+
+    /// Read and decode next message. `None` is returned when source ends
+    /// between messages, end in the middle of a message is an error.
+    pub fn read_message(&mut self) -> io::Result<Option<ValueTreeBuilder>> {
+        let mut prefix = [0u8; 4];
+        let mut cnt = 0;
+        while cnt < prefix.len() {
+            match self.src.read(&mut prefix[cnt..]) {
+                Ok(0) if cnt == 0 => return Ok(None),
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => cnt += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let len = BigEndian::read_u32(&prefix) as usize;
+        if len > self.max_frame_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "frame length exceeds limit",
+            ));
+        }
+
+        self.buffer.resize(len, 0);
+        self.src.read_exact(&mut self.buffer)?;
+
+        parse_frpc(&self.buffer)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn into_inner(self) -> R {
+        self.src
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Value;
+
+    // returns data in given pieces
+    struct Pieces(Vec<Vec<u8>>);
+
+    impl Read for Pieces {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            let n = buf.len().min(self.0[0].len());
+            buf[..n].copy_from_slice(&self.0[0][..n]);
+            self.0[0].drain(..n);
+            if self.0[0].is_empty() {
+                self.0.remove(0);
+            }
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn response_split_across_reads() {
+        // response "abc" with length prefix, second read starts inside of
+        // the prefix
+        let data = hex::decode("0000000aca110300702003616263").unwrap();
+        let reader = Pieces(vec![data[..2].to_vec(), data[2..].to_vec()]);
+        let mut frames = FrameReader::new(reader);
+
+        let tree = frames.read_message().unwrap().unwrap();
+        assert_eq!(tree.values, vec![Value::Str("abc".to_owned())]);
+        assert!(frames.read_message().unwrap().is_none());
+
+        // source ends inside of message
+        let reader = Pieces(vec![data[..2].to_vec(), data[2..10].to_vec()]);
+        let mut frames = FrameReader::new(reader);
+        let err = frames.read_message().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn frame_length_limit() {
+        // prefix announcing 4 GiB - 1 message
+        let reader = Pieces(vec![hex::decode("ffffffff").unwrap()]);
+        let mut frames = FrameReader::new(reader);
+        let err = frames.read_message().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // message of exactly the limit is read
+        let data = hex::decode("0000000aca110300702003616263").unwrap();
+        let mut frames = FrameReader::new(Pieces(vec![data.clone()])).max_frame_len(10);
+        assert!(frames.read_message().unwrap().is_some());
+
+        let mut frames = FrameReader::new(Pieces(vec![data])).max_frame_len(9);
+        let err = frames.read_message().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,21 +1,67 @@
+mod args;
 mod common;
+pub mod constants;
+mod convert;
+mod datetime;
+mod decoder;
+pub mod encoding;
+mod frame;
+mod message_stream;
+mod method_peek;
+mod scalar_args_builder;
+mod schema;
 mod serialize;
+mod tee;
 mod tokenizer;
+mod validator;
 mod value_tree_builder;
-
+mod version;
+
+pub use args::{ArgsError, FromArgs, IntoArgs};
+pub use convert::TypeError;
+pub use datetime::DateTimeVer30;
+pub use decoder::Decoder;
+pub use frame::FrameReader;
+pub use message_stream::MessageStream;
+pub use scalar_args_builder::ScalarArgsBuilder;
+pub use schema::{Field, Kind, Schema, ValidationError};
 pub use serialize::Serializer;
-pub use tokenizer::Tokenizer;
+pub use tee::Tee;
+pub use tokenizer::{Callback, Tokenizer, TokenizerState};
 pub use value_tree_builder::{ParsedStatus, ValueTreeBuilder};
+pub use version::Version;
+
+use common::*;
+use method_peek::MethodPeek;
+use validator::Validator;
 
-use std::collections::{BTreeMap, HashMap};
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt;
 
-#[derive(Debug)]
+/// Struct members are compared regardless of their order.
+///
+/// Values have total order, so arrays can be sorted. Values of different
+/// types are ordered `Null < Bool < Int < Double < DateTime < Str < Binary <
+/// Array < Struct`. Doubles are ordered by `f64::total_cmp` (`-0.0 < 0.0`
+/// and NaN equals itself), arrays lexicographically and structs as their
+/// members sorted by key.
+///
+/// `Debug` output is compact form of `Display` with doubles always having
+/// fraction (`5.0`), escaped strings and binaries longer than 32 bytes
+/// truncated.
+///
+/// `Str` is always valid utf8 as protocol requires, data which may not be
+/// utf8 (e.g. taken from external sources) have to be sent as `Binary`.
+///
+/// Struct keeps all decoded members, so members unknown to application
+/// survive decoding, modification and serializing again. Order of members
+/// is not kept.
 pub enum Value {
     Int(i64),
     Str(String),
     Null,
-    DateTime(i64), // unix timestamp (UTC) can be negative :-)
+    DateTime(DateTimeVer30),
     Struct(HashMap<String, Value>),
     Array(Vec<Value>),
     Double(f64),
@@ -23,17 +69,248 @@ pub enum Value {
     Binary(Vec<u8>),
 }
 
+impl Ord for Value {
+    fn cmp(&self, other: &Value) -> cmp::Ordering {
+        match (self, other) {
+            (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+            (Value::Int(a), Value::Int(b)) => a.cmp(b),
+            (Value::Double(a), Value::Double(b)) => a.total_cmp(b),
+            (Value::DateTime(a), Value::DateTime(b)) => a.cmp(b),
+            (Value::Str(a), Value::Str(b)) => a.cmp(b),
+            (Value::Binary(a), Value::Binary(b)) => a.cmp(b),
+            (Value::Array(a), Value::Array(b)) => a.cmp(b),
+            (Value::Struct(a), Value::Struct(b)) => {
+                // We want sorted according keys so we use BTreeMap
+                let a: BTreeMap<_, _> = a.iter().collect();
+                let b: BTreeMap<_, _> = b.iter().collect();
+                a.cmp(&b)
+            }
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Value) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            // no need to sort members
+            (Value::Struct(a), Value::Struct(b)) => a == b,
+            _ => self.cmp(other) == cmp::Ordering::Equal,
+        }
+    }
+}
+
+impl Eq for Value {}
+
+// Limits of `Value::to_string_limited`
+struct Limits {
+    depth: usize,
+    items: usize,
+    binary_bytes: usize,
+    // `Debug` formatting of doubles and strings
+    debug: bool,
+}
+
+impl Limits {
+    const NONE: Limits = Limits {
+        depth: usize::MAX,
+        items: usize::MAX,
+        binary_bytes: usize::MAX,
+        debug: false,
+    };
+
+    const DEBUG: Limits = Limits {
+        binary_bytes: 32,
+        debug: true,
+        ..Limits::NONE
+    };
+}
+
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", Value::_to_string(self))
+        write!(f, "{}", Value::_to_string(self, 0, &Limits::NONE))
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", Value::_to_string(self, 0, &Limits::DEBUG))
     }
 }
 
 impl Value {
+    /// Name of value type
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            Value::Str(_) => "string",
+            Value::Null => "null",
+            Value::DateTime(_) => "datetime",
+            Value::Struct(_) => "struct",
+            Value::Array(_) => "array",
+            Value::Double(_) => "double",
+            Value::Bool(_) => "bool",
+            Value::Binary(_) => "binary",
+        }
+    }
+
+    /// Type id (without octet count) written by `Serializer` for value in
+    /// protocol `version`. Int of protocol 2.x carries sign in type id.
+    pub fn wire_tag(&self, version: Version) -> u8 {
+        match self {
+            Value::Int(x) if version.major() == 2 => {
+                if *x < 0 {
+                    VINT_ID
+                } else {
+                    U_VINT_ID
+                }
+            }
+            Value::Int(_) => INT_ID,
+            Value::Str(_) => STRING_ID,
+            Value::Null => NULL_ID,
+            Value::DateTime(_) => DATETIME_ID,
+            Value::Struct(_) => STRUCT_ID,
+            Value::Array(_) => ARRAY_ID,
+            Value::Double(_) => DOUBLE_ID,
+            Value::Bool(_) => BOOL_ID,
+            Value::Binary(_) => BIN_ID,
+        }
+    }
+
+    /// Common `type_name` of all array items, `None` for mixed or empty
+    /// array and for other types
+    pub fn array_element_type(&self) -> Option<&'static str> {
+        let items = match self {
+            Value::Array(v) => v,
+            _ => return None,
+        };
+        let name = items.first()?.type_name();
+        if items.iter().all(|x| x.type_name() == name) {
+            Some(name)
+        } else {
+            None
+        }
+    }
+
+    // position of type in total order
+    fn rank(&self) -> u8 {
+        match self {
+            Value::Null => 0,
+            Value::Bool(_) => 1,
+            Value::Int(_) => 2,
+            Value::Double(_) => 3,
+            Value::DateTime(_) => 4,
+            Value::Str(_) => 5,
+            Value::Binary(_) => 6,
+            Value::Array(_) => 7,
+            Value::Struct(_) => 8,
+        }
+    }
+
+    /// Mutable access to struct members, `None` for other types
+    pub fn as_struct_mut(&mut self) -> Option<&mut HashMap<String, Value>> {
+        match self {
+            Value::Struct(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Remove struct members for which `f` returns false, other types are
+    /// kept untouched
+    pub fn retain_struct(&mut self, mut f: impl FnMut(&str, &Value) -> bool) {
+        if let Value::Struct(v) = self {
+            v.retain(|k, x| f(k, x));
+        }
+    }
+
+    /// Mutable access to array items, `None` for other types
+    pub fn as_array_mut(&mut self) -> Option<&mut Vec<Value>> {
+        match self {
+            Value::Array(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Create UTC datetime from unix timestamp
+    pub fn datetime_from_unix(unix_time: i64) -> Value {
+        Value::DateTime(DateTimeVer30::from_unix(unix_time, 0))
+    }
+
+    /// Create datetime from calendar fields in local time of `time_zone`
+    /// (in quarters of hour, from -12:00 to +14:00), week day and unix
+    /// timestamp are computed. Year has to fit into protocol (1600-3647).
+    pub fn datetime(
+        year: u16,
+        month: u8,
+        day: u8,
+        hour: u8,
+        min: u8,
+        sec: u8,
+        time_zone: i8,
+    ) -> Result<Value, &'static str> {
+        if !(-48..=56).contains(&time_zone) {
+            return Err("time zone out of range");
+        }
+        if !(serialize::MIN_DATETIME_YEAR..=serialize::MAX_DATETIME_YEAR).contains(&year) {
+            return Err("year out of range");
+        }
+        if !(1..=12).contains(&month) {
+            return Err("month out of range");
+        }
+        let date =
+            time::Date::try_from_ymd(year as i32, month, day).map_err(|_| "day out of range")?;
+        let local = date
+            .try_with_hms(hour, min, sec)
+            .map_err(|_| "time out of range")?;
+
+        let unix_time = local.assume_utc().timestamp() - (time_zone as i64) * 15 * 60;
+        let dt = DateTimeVer30::from_unix(unix_time, time_zone);
+        Ok(Value::DateTime(dt))
+    }
+
+    /// Format value like `to_string` but elide containers nested deeper than
+    /// `max_depth`, array items and struct members over `max_items` and
+    /// binary bytes over `max_binary_bytes` by `...`. Meant for logs.
+    pub fn to_string_limited(
+        &self,
+        max_depth: usize,
+        max_items: usize,
+        max_binary_bytes: usize,
+    ) -> String {
+        let limits = Limits {
+            depth: max_depth,
+            items: max_items,
+            binary_bytes: max_binary_bytes,
+            debug: false,
+        };
+        Value::_to_string(self, 0, &limits)
+    }
+
+    /// Serialize value in canonical form, e.g. for signing. Struct members
+    /// are ordered by key and integers use minimal width of protocol 3.0,
+    /// so equal values always give the same bytes.
+    pub fn canonical_bytes(&self) -> Result<Vec<u8>, &'static str> {
+        let mut dst = vec![];
+        Serializer::new()
+            .with_version(Version::V30)
+            .sort_keys(true)
+            .append_value(&mut dst, self)?;
+        Ok(dst)
+    }
+
     // recursive implementation
-    fn _to_string(val: &Value) -> String {
+    fn _to_string(val: &Value, depth: usize, limits: &Limits) -> String {
         match val {
             Value::Int(v) => v.to_string(),
+            // `f64` Display prints shortest representation which parses back
+            // to the same value, tests comparing output rely on this
+            Value::Double(v) if limits.debug => format!("{:?}", v),
             Value::Double(v) => v.to_string(),
             Value::Null => "null".to_owned(),
             Value::Bool(v) => {
@@ -43,58 +320,523 @@ impl Value {
                     "false".to_owned()
                 }
             }
-            Value::DateTime(v) => {
-                // time::PrimitiveDateTime::from_unix_timestamp(*v).format("%Y-%m-%d %H:%M:%S")
-                time::OffsetDateTime::from_unix_timestamp(*v).format("%Y-%m-%d %H:%M:%S")
-            }
+            Value::DateTime(v) => v.to_string(),
+            Value::Str(v) if limits.debug => format!("{:?}", v),
             Value::Str(v) => "\"".to_owned() + v + &"\"".to_owned(),
+            Value::Binary(v) if v.len() > limits.binary_bytes => {
+                "b\"".to_owned() + &hex::encode(&v[..limits.binary_bytes]) + "...\""
+            }
             Value::Binary(v) => "b\"".to_owned() + &hex::encode(v) + &"\"".to_owned(),
+            Value::Array(_) if depth >= limits.depth => "(...)".to_owned(),
             Value::Array(v) => {
-                let mut cnt: usize = 0;
-                let total = v.len();
-
-                "(".to_owned()
-                    + &v.iter()
-                        .map(|x| {
-                            cnt += 1;
-                            if cnt < total {
-                                Value::to_string(&x) + &", ".to_owned()
-                            } else {
-                                Value::to_string(&x)
-                            }
-                        })
-                        .collect::<String>()
-                    + &")".to_owned()
+                let mut items: Vec<_> = v
+                    .iter()
+                    .take(limits.items)
+                    .map(|x| Value::_to_string(x, depth + 1, limits))
+                    .collect();
+                if v.len() > limits.items {
+                    items.push("...".to_owned());
+                }
+
+                "(".to_owned() + &items.join(", ") + &")".to_owned()
             }
+            Value::Struct(_) if depth >= limits.depth => "{...}".to_owned(),
             Value::Struct(v) => {
-                let mut cnt: usize = 0;
-                let total = v.len();
+                // We want sorted according keys so we use BTreeMap
+                let sorted: BTreeMap<_, _> = v.iter().collect();
+                let mut items: Vec<_> = sorted
+                    .iter()
+                    .take(limits.items)
+                    .map(|(k, x)| {
+                        (*k).to_string() + ": " + &Value::_to_string(x, depth + 1, limits)
+                    })
+                    .collect();
+                if v.len() > limits.items {
+                    items.push("...".to_owned());
+                }
 
+                "{".to_owned() + &items.join(", ") + &"}".to_owned()
+            }
+        }
+    }
+
+    /// Call `f` for every value in the tree together with its path. Struct
+    /// members are joined by `.` and array items are indexed by `[n]`, e.g.
+    /// `result.items[0].id`. Path of the root value is empty string. Struct
+    /// members are visited in sorted key order.
+    pub fn walk<'a, F>(&'a self, mut f: F)
+    where
+        F: FnMut(&str, &'a Value),
+    {
+        let mut path = String::new();
+        Value::_walk(self, &mut path, &mut f);
+    }
+
+    // recursive implementation
+    fn _walk<'a, F>(val: &'a Value, path: &mut String, f: &mut F)
+    where
+        F: FnMut(&str, &'a Value),
+    {
+        f(path, val);
+
+        let len = path.len();
+        match val {
+            Value::Array(v) => {
+                for (i, x) in v.iter().enumerate() {
+                    push_index(path, i);
+                    Value::_walk(x, path, f);
+                    path.truncate(len);
+                }
+            }
+            Value::Struct(v) => {
                 // We want sorted according keys so we use BTreeMap
                 let v: BTreeMap<_, _> = v.iter().collect();
-                "{".to_owned()
-                    + &v.iter()
-                        .map(|(k, x)| {
-                            cnt += 1;
+                for (k, x) in v {
+                    push_key(path, k);
+                    Value::_walk(x, path, f);
+                    path.truncate(len);
+                }
+            }
+            _ => {}
+        }
+    }
 
-                            if cnt < total {
-                                (*k).to_string() + ": " + &Value::to_string(&x) + ", "
-                            } else {
-                                (*k).to_string() + ": " + &Value::to_string(&x)
-                            }
-                        })
-                        .collect::<String>()
-                    + &"}".to_owned()
+    /// Same as `walk` but `f` can modify values. Children are visited after
+    /// `f` returns, so they reflect its changes (e.g. removed struct member
+    /// is not visited).
+    pub fn walk_mut<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&str, &mut Value),
+    {
+        let mut path = String::new();
+        Value::_walk_mut(self, &mut path, &mut f);
+    }
+
+    // recursive implementation
+    fn _walk_mut<F>(val: &mut Value, path: &mut String, f: &mut F)
+    where
+        F: FnMut(&str, &mut Value),
+    {
+        f(path, val);
+
+        let len = path.len();
+        match val {
+            Value::Array(v) => {
+                for (i, x) in v.iter_mut().enumerate() {
+                    push_index(path, i);
+                    Value::_walk_mut(x, path, f);
+                    path.truncate(len);
+                }
+            }
+            Value::Struct(v) => {
+                // We want sorted according keys so we use BTreeMap
+                let v: BTreeMap<_, _> = v.iter_mut().collect();
+                for (k, x) in v {
+                    push_key(path, k);
+                    Value::_walk_mut(x, path, f);
+                    path.truncate(len);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Convert every datetime in the tree to UTC, calendar fields are
+    /// recomputed from timestamp. Datetimes with invalid calendar fields and
+    /// no timestamp are kept as they are.
+    pub fn normalize_datetimes_to_utc(&mut self) {
+        self.walk_mut(|_, v| {
+            if let Value::DateTime(dt) = v {
+                if let Some(timestamp) = dt.timestamp() {
+                    *dt = DateTimeVer30::from_unix(timestamp, 0);
+                }
+            }
+        });
+    }
+
+    /// Return path (see `walk`) and value of every scalar in the tree. Empty
+    /// arrays and structs have no leaves so they don't appear in output.
+    pub fn flatten(&self) -> Vec<(String, &Value)> {
+        let mut leaves = vec![];
+        self.walk(|path, v| match v {
+            Value::Array(_) | Value::Struct(_) => {}
+            _ => leaves.push((path.to_owned(), v)),
+        });
+        leaves
+    }
+
+    /// Count of all values in the tree, each container counts as one node
+    /// plus its items. Useful to limit complexity of received arguments.
+    pub fn count_nodes(&self) -> usize {
+        let mut count = 0;
+        self.walk(|_, _| count += 1);
+        count
+    }
+
+    /// Compare `self` (old) with `other` (new) and return every difference
+    /// with its path (see `walk`). Struct members are compared in sorted key
+    /// order, array items by index.
+    pub fn diff<'a>(&'a self, other: &'a Value) -> Vec<Diff<'a>> {
+        let mut diffs = vec![];
+        let mut path = String::new();
+        Value::_diff(self, other, &mut path, &mut diffs);
+        diffs
+    }
+
+    // recursive implementation
+    fn _diff<'a>(old: &'a Value, new: &'a Value, path: &mut String, diffs: &mut Vec<Diff<'a>>) {
+        let len = path.len();
+        match (old, new) {
+            (Value::Array(a), Value::Array(b)) => {
+                if a.len() != b.len() {
+                    diffs.push(Diff::ArrayLen {
+                        path: path.clone(),
+                        old: a.len(),
+                        new: b.len(),
+                    });
+                }
+                for i in 0..cmp::max(a.len(), b.len()) {
+                    push_index(path, i);
+                    match (a.get(i), b.get(i)) {
+                        (Some(x), Some(y)) => Value::_diff(x, y, path, diffs),
+                        (Some(x), None) => diffs.push(Diff::Removed {
+                            path: path.clone(),
+                            value: x,
+                        }),
+                        (None, Some(y)) => diffs.push(Diff::Added {
+                            path: path.clone(),
+                            value: y,
+                        }),
+                        (None, None) => unreachable!(),
+                    }
+                    path.truncate(len);
+                }
+            }
+            (Value::Struct(a), Value::Struct(b)) => {
+                // We want sorted according keys so we use BTreeSet
+                let keys: BTreeSet<_> = a.keys().chain(b.keys()).collect();
+                for k in keys {
+                    push_key(path, k);
+                    match (a.get(k), b.get(k)) {
+                        (Some(x), Some(y)) => Value::_diff(x, y, path, diffs),
+                        (Some(x), None) => diffs.push(Diff::Removed {
+                            path: path.clone(),
+                            value: x,
+                        }),
+                        (None, Some(y)) => diffs.push(Diff::Added {
+                            path: path.clone(),
+                            value: y,
+                        }),
+                        (None, None) => unreachable!(),
+                    }
+                    path.truncate(len);
+                }
+            }
+            _ => {
+                if old != new {
+                    diffs.push(Diff::Changed {
+                        path: path.clone(),
+                        old,
+                        new,
+                    });
+                }
             }
         }
     }
 }
 
+/// Single difference found by `Value::diff`
+#[derive(Debug, PartialEq)]
+pub enum Diff<'a> {
+    /// Value differs (or has different type)
+    Changed {
+        path: String,
+        old: &'a Value,
+        new: &'a Value,
+    },
+    /// Struct member or array item exists only in new value
+    Added { path: String, value: &'a Value },
+    /// Struct member or array item exists only in old value
+    Removed { path: String, value: &'a Value },
+    /// Array has different length
+    ArrayLen {
+        path: String,
+        old: usize,
+        new: usize,
+    },
+}
+
+// append array index to value path
+fn push_index(path: &mut String, i: usize) {
+    path.push_str(&format!("[{}]", i));
+}
+
+// append struct key to value path
+fn push_key(path: &mut String, key: &str) {
+    if !path.is_empty() {
+        path.push('.');
+    }
+    path.push_str(key);
+}
+
+/// Error returned by one-shot decoding helpers
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// Position in data where tokenizer stopped
+    pub pos: usize,
+    /// Reason reported by tokenizer
+    pub msg: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at byte {}", self.msg, self.pos)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn parse_all(tokenizer: Tokenizer, data: &[u8]) -> Result<ValueTreeBuilder, ParseError> {
+    let mut tokenizer = tokenizer.input_length(data.len());
+    let mut tree = ValueTreeBuilder::new();
+
+    match tokenizer.parse(data, &mut tree) {
+        Ok((true, _)) => Err(ParseError {
+            pos: data.len(),
+            msg: match tokenizer.open_container() {
+                Some(container) => format!("unexpected data end, incomplete {}", container),
+                None => "unexpected data end".to_owned(),
+            },
+        }),
+        Ok((false, processed)) if processed < data.len() => Err(ParseError {
+            pos: processed,
+            msg: "data after end".to_owned(),
+        }),
+        Ok(_) => Ok(tree),
+        Err(pos) => {
+            let msg = match &tree.what {
+                ParsedStatus::Error(msg) => msg.clone(),
+                _ => "invalid data".to_owned(),
+            };
+            Err(ParseError { pos, msg })
+        }
+    }
+}
+
+/// Decode complete FRPC message from `data`.
+pub fn parse_frpc(data: &[u8]) -> Result<ValueTreeBuilder, ParseError> {
+    parse_all(Tokenizer::new_frpc(), data)
+}
+
+/// Decode complete FRPS message from `data`.
+pub fn parse_frps(data: &[u8]) -> Result<ValueTreeBuilder, ParseError> {
+    parse_all(Tokenizer::new_frps(), data)
+}
+
+/// Check that `data` is single complete FRPC (or FRPS when `is_frps`)
+/// message without trailing data. Values are not built, so it is cheaper
+/// than `parse_frpc` accepting the same messages.
+pub fn is_valid(data: &[u8], is_frps: bool) -> bool {
+    let tokenizer = if is_frps {
+        Tokenizer::new_frps()
+    } else {
+        Tokenizer::new_frpc()
+    };
+    let mut tokenizer = tokenizer.input_length(data.len());
+    let mut validator = Validator::default();
+    tokenizer.parse(data, &mut validator) == Ok((false, data.len()))
+}
+
+/// Return method name of FRPC/FRPS call in `data` without decoding its
+/// arguments, e.g. for routing. `None` is returned for response and fault.
+pub fn peek_method(data: &[u8]) -> Result<Option<&str>, ParseError> {
+    let mut tokenizer = Tokenizer::new_frpc();
+    let mut peek = MethodPeek::default();
+    let res = tokenizer.parse(data, &mut peek);
+    if peek.no_call {
+        return Ok(None);
+    }
+    if let Some(length) = peek.name_length {
+        // name follows magic, version, type and name length, it was
+        // validated by tokenizer
+        return Ok(Some(std::str::from_utf8(&data[6..6 + length]).unwrap()));
+    }
+
+    match res {
+        Err(pos) => Err(ParseError {
+            pos,
+            msg: peek.error.unwrap_or_else(|| "invalid data".to_owned()),
+        }),
+        Ok(_) => Err(ParseError {
+            pos: data.len(),
+            msg: "unexpected data end".to_owned(),
+        }),
+    }
+}
+
+/// Decode single FRPS data block (header and payload) and return payload.
+pub fn parse_frps_data(data: &[u8]) -> Result<Vec<u8>, ParseError> {
+    let tree = parse_all(Tokenizer::new_frps_data(), data)?;
+    match tree.what {
+        ParsedStatus::Fault => Err(ParseError {
+            pos: data.len(),
+            msg: format!("fault({}, {})", tree.values[0], tree.values[1]),
+        }),
+        _ => Ok(tree.data),
+    }
+}
+
+/// Format bytes of `data` at most `radius` bytes around `pos` as hex with
+/// caret under byte at `pos`, e.g. for error position reported by tokenizer:
+///
+/// ```text
+/// 0: ca 12 03 00
+///       ^^
+/// ```
+pub fn error_context(data: &[u8], pos: usize, radius: usize) -> String {
+    let start = pos.saturating_sub(radius).min(data.len());
+    let end = pos.saturating_add(radius).saturating_add(1).min(data.len());
+
+    let bytes: Vec<_> = data[start..end]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let offset = format!("{}: ", start);
+    // caret can point behind the last byte when data ended unexpectedly
+    let indent = offset.len() + (pos - start) * 3;
+
+    format!("{}{}\n{}^^", offset, bytes.join(" "), " ".repeat(indent))
+}
+
+/// Serialize complete method call in protocol `version`, e.g.
+/// `encode_call(Version::V30, "user.get", (5, "name"))`.
+pub fn encode_call<A: IntoArgs>(
+    version: Version,
+    method: &str,
+    args: A,
+) -> Result<Vec<u8>, &'static str> {
+    let mut dst = vec![];
+    Serializer::new()
+        .with_version(version)
+        .append_call(&mut dst, method, args)?;
+    Ok(dst)
+}
+
+/// Error returned by `transcode`
+#[derive(Debug, Clone, PartialEq)]
+pub enum TranscodeError {
+    /// Source message can't be decoded
+    Parse(ParseError),
+    /// Message can't be written in target version, e.g. null in 1.0
+    Serialize(&'static str),
+}
+
+impl fmt::Display for TranscodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TranscodeError::Parse(e) => write!(f, "{}", e),
+            TranscodeError::Serialize(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for TranscodeError {}
+
+/// Decode FRPC message of any supported version and write it again in
+/// `target` version.
+pub fn transcode(data: &[u8], target: Version) -> Result<Vec<u8>, TranscodeError> {
+    let mut tree = parse_frpc(data).map_err(TranscodeError::Parse)?;
+
+    let mut dst = vec![];
+    let mut serializer = Serializer::new().with_version(target);
+    let res = match tree.what {
+        ParsedStatus::MethodCall(ref name) => {
+            let args = std::mem::take(&mut tree.values);
+            serializer.append_call(&mut dst, name, args)
+        }
+        ParsedStatus::Fault => match tree.values.as_slice() {
+            [Value::Int(code), Value::Str(msg)] => serializer.append_fault(&mut dst, *code, msg),
+            _ => Err("invalid fault"),
+        },
+        _ => serializer.append_response(&mut dst, &tree.values[0]),
+    };
+    res.map_err(TranscodeError::Serialize)?;
+    Ok(dst)
+}
+
+/// Decode two FRPC messages and compare them. Messages are equivalent when
+/// they have the same type and values even if struct members were serialized
+/// in different order.
+pub fn equivalent(a: &[u8], b: &[u8]) -> Result<bool, ParseError> {
+    let a = parse_frpc(a)?;
+    let b = parse_frpc(b)?;
+
+    Ok(a.what == b.what && a.values == b.values)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use enum_extract::let_extract;
 
+    #[test]
+    fn wire_tags() {
+        let values = vec![
+            (Value::Int(5), U_VINT_ID, INT_ID),
+            (Value::Int(-5), VINT_ID, INT_ID),
+            (Value::Str("a".to_owned()), STRING_ID, STRING_ID),
+            (Value::Null, NULL_ID, NULL_ID),
+            (Value::datetime_from_unix(0), DATETIME_ID, DATETIME_ID),
+            (Value::Struct(HashMap::new()), STRUCT_ID, STRUCT_ID),
+            (Value::Array(vec![]), ARRAY_ID, ARRAY_ID),
+            (Value::Double(0.5), DOUBLE_ID, DOUBLE_ID),
+            (Value::Bool(true), BOOL_ID, BOOL_ID),
+            (Value::Binary(vec![1]), BIN_ID, BIN_ID),
+        ];
+        for (value, tag_v21, tag_v30) in &values {
+            assert_eq!(value.wire_tag(Version::V21), *tag_v21);
+            assert_eq!(value.wire_tag(Version::V30), *tag_v30);
+
+            // serializer writes the same type id
+            for version in &[Version::V21, Version::V30] {
+                let data = Serializer::new()
+                    .with_version(*version)
+                    .to_exact_vec(value)
+                    .unwrap();
+                assert_eq!(data[5] & TYPE_MASK, value.wire_tag(*version));
+            }
+        }
+        assert_eq!(Value::Int(-5).wire_tag(Version::V10), INT_ID);
+    }
+
+    #[test]
+    fn unknown_members_round_trip() {
+        // response from newer peer with members unknown to us
+        let mut nested = HashMap::new();
+        nested.insert("flag".to_owned(), Value::Bool(true));
+        let mut members = HashMap::new();
+        members.insert("id".to_owned(), Value::Int(1));
+        members.insert("added".to_owned(), Value::Str("x".to_owned()));
+        members.insert("nested".to_owned(), Value::Struct(nested));
+        let data = Serializer::new()
+            .to_exact_vec(&Value::Struct(members))
+            .unwrap();
+
+        let mut tree = parse_frpc(&data).unwrap();
+        let mut value = tree.values.remove(0);
+        value
+            .as_struct_mut()
+            .unwrap()
+            .insert("id".to_owned(), Value::Int(2));
+        let data = Serializer::new().to_exact_vec(&value).unwrap();
+
+        let tree = parse_frpc(&data).unwrap();
+        assert_eq!(
+            tree.values[0].to_string(),
+            r#"{added: "x", id: 2, nested: {flag: true}}"#
+        );
+    }
+
     #[test]
     fn tokenize() {
         // serialized frps data
@@ -134,6 +876,7 @@ mod tests {
             println!("Tokenizer returned: {}", e);
         }
         assert_eq!(res.is_ok(), true);
+        assert_eq!(tree.fault_parts(), Some((500, "FAULT_TEST")));
         // dbg!(buffer, tree.values);
     }
 
@@ -196,6 +939,86 @@ mod tests {
         }
     }
 
+    #[test]
+    fn equivalent_structs_with_swapped_keys() {
+        // {a: 1, b: 2} and {b: 2, a: 1}
+        let a = hex::decode("ca1103007050020161080201620804").unwrap();
+        let b = hex::decode("ca1103007050020162080401610802").unwrap();
+        assert_ne!(a, b);
+        assert_eq!(equivalent(&a, &b), Ok(true));
+
+        // {a: 1, b: 3}
+        let c = hex::decode("ca1103007050020161080201620806").unwrap();
+        assert_eq!(equivalent(&a, &c), Ok(false));
+
+        let err = equivalent(&a, &c[..12]).unwrap_err();
+        assert_eq!(err.msg, "unexpected data end, incomplete struct");
+    }
+
+    #[test]
+    fn count_nodes_nested() {
+        // {user: {id: 1, tags: ["a", "b", []]}, ok: true}
+        let mut user = HashMap::new();
+        user.insert("id".to_owned(), Value::Int(1));
+        user.insert(
+            "tags".to_owned(),
+            Value::Array(vec![
+                Value::Str("a".to_owned()),
+                Value::Str("b".to_owned()),
+                Value::Array(vec![]),
+            ]),
+        );
+        let mut root = HashMap::new();
+        root.insert("user".to_owned(), Value::Struct(user));
+        root.insert("ok".to_owned(), Value::Bool(true));
+
+        // root, user, id, tags, "a", "b", [], ok
+        assert_eq!(Value::Struct(root).count_nodes(), 8);
+        assert_eq!(Value::Null.count_nodes(), 1);
+    }
+
+    #[test]
+    fn flatten_nested_struct_with_array() {
+        let mut first = HashMap::new();
+        first.insert("id".to_owned(), Value::Int(5));
+        let mut second = HashMap::new();
+        second.insert("id".to_owned(), Value::Int(6));
+        second.insert("name".to_owned(), Value::Str("x".to_owned()));
+
+        let mut result = HashMap::new();
+        result.insert(
+            "items".to_owned(),
+            Value::Array(vec![Value::Struct(first), Value::Struct(second)]),
+        );
+        result.insert("status".to_owned(), Value::Int(200));
+        result.insert("empty".to_owned(), Value::Array(vec![]));
+
+        let mut root = HashMap::new();
+        root.insert("result".to_owned(), Value::Struct(result));
+        let root = Value::Struct(root);
+
+        let leaves = root.flatten();
+        let paths: Vec<_> = leaves.iter().map(|(p, _)| p.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "result.items[0].id",
+                "result.items[1].id",
+                "result.items[1].name",
+                "result.status"
+            ]
+        );
+        assert_eq!(leaves[0].1, &Value::Int(5));
+        assert_eq!(leaves[2].1, &Value::Str("x".to_owned()));
+        assert_eq!(leaves[3].1, &Value::Int(200));
+
+        // scalar root has empty path
+        assert_eq!(
+            Value::Bool(true).flatten(),
+            vec![("".to_owned(), &Value::Bool(true))]
+        );
+    }
+
     use std::env;
     use std::fs::File;
     use std::io::{self, prelude::*, BufReader};
@@ -275,6 +1098,17 @@ mod tests {
         Ok(())
     }
 
+    // tests named "... (lenient datetime)" accept 3.0 datetime in older
+    // messages
+    fn fixture_tokenizer(test_name: &str, is_frps: bool) -> Tokenizer {
+        let tokenizer = if is_frps {
+            tokenizer::Tokenizer::new_frps()
+        } else {
+            tokenizer::Tokenizer::new_frpc()
+        };
+        tokenizer.lenient_datetime(test_name.contains("(lenient datetime)"))
+    }
+
     fn test_by_chunk(
         order: &i32,
         line: &i32,
@@ -284,11 +1118,7 @@ mod tests {
         binary_data: &String,
         is_frps: bool,
     ) {
-        let mut tokenizer = if is_frps {
-            tokenizer::Tokenizer::new_frps()
-        } else {
-            tokenizer::Tokenizer::new_frpc()
-        };
+        let mut tokenizer = fixture_tokenizer(test_name, is_frps);
         let mut call = value_tree_builder::ValueTreeBuilder::new();
         let mut in_string = false;
         // println!(
@@ -381,11 +1211,7 @@ mod tests {
         binary_data: &String,
         is_frps: bool,
     ) {
-        let mut tokenizer = if is_frps {
-            tokenizer::Tokenizer::new_frps()
-        } else {
-            tokenizer::Tokenizer::new_frpc()
-        };
+        let mut tokenizer = fixture_tokenizer(test_name, is_frps);
 
         let mut call = value_tree_builder::ValueTreeBuilder::new();
         let mut in_string = false;
@@ -510,11 +1336,7 @@ mod tests {
             .filter(|&c| !c.is_whitespace())
             .collect();
 
-        let mut tokenizer = if is_frps {
-            tokenizer::Tokenizer::new_frps()
-        } else {
-            tokenizer::Tokenizer::new_frpc()
-        };
+        let mut tokenizer = fixture_tokenizer(test_name, is_frps);
 
         let mut call = value_tree_builder::ValueTreeBuilder::new();
         let res = tokenizer.parse(&data[0..], &mut call);
@@ -639,4 +1461,500 @@ mod tests {
         let res = test_file("tests/frps.tests", true, &test_serialize_deserialize);
         assert!(res.is_ok());
     }
+
+    #[test]
+    fn diff_nested_field_and_added_key() {
+        let mut user = HashMap::new();
+        user.insert("id".to_owned(), Value::Int(5));
+        user.insert("name".to_owned(), Value::Str("x".to_owned()));
+        let mut old = HashMap::new();
+        old.insert("user".to_owned(), Value::Struct(user));
+        old.insert("tags".to_owned(), Value::Array(vec![Value::Int(1)]));
+        let old = Value::Struct(old);
+
+        let mut user = HashMap::new();
+        user.insert("id".to_owned(), Value::Int(5));
+        user.insert("name".to_owned(), Value::Str("y".to_owned()));
+        let mut new = HashMap::new();
+        new.insert("user".to_owned(), Value::Struct(user));
+        new.insert("tags".to_owned(), Value::Array(vec![Value::Int(1)]));
+        new.insert("status".to_owned(), Value::Int(200));
+        let new = Value::Struct(new);
+
+        assert_eq!(
+            old.diff(&new),
+            vec![
+                Diff::Added {
+                    path: "status".to_owned(),
+                    value: &Value::Int(200)
+                },
+                Diff::Changed {
+                    path: "user.name".to_owned(),
+                    old: &Value::Str("x".to_owned()),
+                    new: &Value::Str("y".to_owned())
+                },
+            ]
+        );
+        assert!(old.diff(&old).is_empty());
+    }
+
+    #[test]
+    fn redact_and_serialize() {
+        let mut user = HashMap::new();
+        user.insert("name".to_owned(), Value::Str("x".to_owned()));
+        user.insert("password".to_owned(), Value::Str("secret".to_owned()));
+        let mut root = HashMap::new();
+        root.insert("users".to_owned(), Value::Array(vec![Value::Struct(user)]));
+        root.insert("password".to_owned(), Value::Str("secret".to_owned()));
+        let mut root = Value::Struct(root);
+
+        if let Some(s) = root.as_struct_mut() {
+            s.remove("password");
+        }
+        assert_eq!(root.as_array_mut(), None);
+
+        let mut paths = vec![];
+        root.walk_mut(|path, v| {
+            if let Some(s) = v.as_struct_mut() {
+                s.remove("password");
+            }
+            if let Some(a) = v.as_array_mut() {
+                a.push(Value::Null);
+            }
+            paths.push(path.to_owned());
+        });
+        assert_eq!(
+            paths,
+            vec!["", "users", "users[0]", "users[0].name", "users[1]"]
+        );
+
+        let mut serializer = Serializer::new();
+        let mut buffer = [0u8; 64];
+        let cnt = serializer.write_response(&mut buffer, &root).unwrap();
+        let tree = parse_frpc(&buffer[..cnt]).unwrap();
+        assert_eq!(tree.to_string(), "{users: ({name: \"x\"}, null)}");
+    }
+
+    #[test]
+    fn valid_message() {
+        // m(1, "ab", true, null), call can end after any argument
+        let data = hex::decode("ca11030068016d0802200261621160").unwrap();
+        assert!(is_valid(&data[..data.len() - 2], false));
+        assert!(is_valid(&data, false));
+        assert!(!is_valid(&data[..12], false));
+
+        // response "abc" with trailing byte
+        let data = hex::decode("ca110300702003616263ff").unwrap();
+        assert!(is_valid(&data[..10], false));
+        assert!(!is_valid(&data, false));
+
+        // invalid utf8 in string and struct key
+        assert!(!is_valid(&hex::decode("ca110300702001ff").unwrap(), false));
+        let data = hex::decode("ca11030070500101ff60").unwrap();
+        assert!(!is_valid(&data, false));
+        assert!(parse_frpc(&data).is_err());
+
+        // response with stream data
+        let data = hex::decode("ca1102017020017801030061626300").unwrap();
+        assert!(is_valid(&data[..data.len() - 1], true));
+        assert!(!is_valid(&data[..data.len() - 1], false));
+    }
+
+    #[test]
+    fn truncated_array() {
+        // array declaring 3 items, only (1, 2) follow
+        let data = hex::decode("ca11030070580308020804").unwrap();
+        let err = parse_frpc(&data).unwrap_err();
+        assert_eq!(err.pos, data.len());
+        assert_eq!(err.msg, "unexpected data end, incomplete array");
+
+        // inner array is complete, outer is not
+        let data = hex::decode("ca1103007058025801080208").unwrap();
+        let err = parse_frpc(&data).unwrap_err();
+        assert_eq!(err.msg, "unexpected data end, incomplete array");
+
+        // truncated integer outside of containers
+        let data = hex::decode("ca110300700901").unwrap();
+        let err = parse_frpc(&data).unwrap_err();
+        assert_eq!(err.msg, "unexpected data end");
+    }
+
+    #[test]
+    fn homogeneous_array() {
+        let ints = Value::Array(vec![Value::Int(1), Value::Int(2)]);
+        assert_eq!(ints.array_element_type(), Some("int"));
+
+        let mixed = Value::Array(vec![Value::Int(1), Value::Double(2.0)]);
+        assert_eq!(mixed.array_element_type(), None);
+
+        assert_eq!(Value::Array(vec![]).array_element_type(), None);
+        assert_eq!(Value::Int(1).array_element_type(), None);
+    }
+
+    #[test]
+    fn compact_debug() {
+        let mut members = HashMap::new();
+        members.insert("b".to_owned(), Value::Binary(vec![0xab; 40]));
+        members.insert("a".to_owned(), Value::Str("x\"y".to_owned()));
+        let value = Value::Array(vec![Value::Double(5.0), Value::Struct(members)]);
+        assert_eq!(
+            format!("{:?}", value),
+            format!("(5.0, {{a: \"x\\\"y\", b: b\"{}...\"}})", "ab".repeat(32))
+        );
+        assert_eq!(
+            value.to_string(),
+            format!("(5, {{a: \"x\"y\", b: b\"{}\"}})", "ab".repeat(40))
+        );
+    }
+
+    #[test]
+    fn datetimes_to_utc() {
+        // 2017-01-27 12:39:19 UTC in +1:00 and -2:00 (given by fields only)
+        let mut members = HashMap::new();
+        members.insert(
+            "a".to_owned(),
+            Value::DateTime(DateTimeVer30::from_unix(1485520759, 4)),
+        );
+        let local = DateTimeVer30 {
+            unix_time: -1,
+            ..DateTimeVer30::from_unix(1485520759, -8)
+        };
+        members.insert("b".to_owned(), vec![Value::DateTime(local)].into());
+        let mut value = Value::Struct(members);
+
+        let datetimes = |value: &Value| {
+            let mut fields = vec![];
+            value.walk(|_, v| {
+                if let Value::DateTime(dt) = v {
+                    fields.push((dt.time_zone, dt.unix_time, dt.hour));
+                }
+            });
+            fields
+        };
+        assert_eq!(datetimes(&value), vec![(4, 1485520759, 13), (-8, -1, 10)]);
+
+        value.normalize_datetimes_to_utc();
+        assert_eq!(
+            datetimes(&value),
+            vec![(0, 1485520759, 12), (0, 1485520759, 12)]
+        );
+    }
+
+    #[test]
+    fn retain_struct_members() {
+        // {a: 1, _debug: "x", b: {_t: 2}}
+        let data =
+            hex::decode("ca11030070500301610802065f646562756720017801625001025f740804").unwrap();
+        let mut tree = parse_frpc(&data).unwrap();
+        let mut value = tree.values.remove(0);
+        value.retain_struct(|k, _| !k.starts_with('_'));
+
+        let mut serializer = Serializer::new();
+        let mut buffer = [0u8; 64];
+        let cnt = serializer.write_response(&mut buffer, &value).unwrap();
+        let tree = parse_frpc(&buffer[..cnt]).unwrap();
+        let_extract!(Value::Struct(members), &tree.values[0], unreachable!());
+        assert_eq!(members.len(), 2);
+        assert_eq!(members["a"], Value::Int(1));
+        // only top level members are filtered
+        assert_eq!(members["b"].to_string(), "{_t: 2}");
+
+        let mut value = Value::Int(1);
+        value.retain_struct(|_, _| false);
+        assert_eq!(value, Value::Int(1));
+    }
+
+    #[test]
+    fn double_round_trip() {
+        let mut buffer = [0u8; 16];
+
+        for &v in &[0.1, 1e308, -0.0, f64::MIN_POSITIVE, 1.0 / 3.0] {
+            let value = Value::Double(v);
+            let mut serializer = Serializer::new();
+            let cnt = serializer.write_response(&mut buffer, &value).unwrap();
+            let tree = parse_frpc(&buffer[..cnt]).unwrap();
+
+            let_extract!(Value::Double(d), &tree.values[0], unreachable!());
+            assert_eq!(d.to_bits(), v.to_bits());
+            assert_eq!(tree.values[0].to_string().parse::<f64>(), Ok(v));
+        }
+        assert_eq!(Value::Double(0.1).to_string(), "0.1");
+        assert_eq!(Value::Double(-0.0).to_string(), "-0");
+    }
+
+    #[test]
+    fn standalone_data_block() {
+        let payload = b"some stream data";
+        let mut buffer = [0u8; 32];
+        let mut serializer = Serializer::new();
+        let cnt = serializer.write_data(&mut buffer, payload).unwrap();
+
+        assert_eq!(parse_frps_data(&buffer[..cnt]), Ok(payload.to_vec()));
+
+        let err = parse_frps_data(&buffer[..cnt - 1]).unwrap_err();
+        assert_eq!(err.msg, "unexpected data end");
+
+        let err = parse_frps_data(&buffer[..cnt + 1]).unwrap_err();
+        assert_eq!((err.pos, err.msg.as_str()), (cnt, "data after end"));
+
+        // empty block
+        assert_eq!(parse_frps_data(&[0x00]), Ok(vec![]));
+    }
+
+    #[test]
+    fn inflated_length() {
+        // response with array of 1 000 000 items, struct of 5 members,
+        // string of 5 bytes and binary of 5 bytes
+        for (data, msg) in &[
+            ("ca110300705a40420f0802", "array length exceeds input"),
+            ("ca110300705005016108020162", "struct length exceeds input"),
+            ("ca11030070200561626364", "string length exceeds input"),
+            ("ca11030070300561626364", "binary length exceeds input"),
+        ] {
+            let data = hex::decode(data).unwrap();
+            let err = parse_frpc(&data).unwrap_err();
+            assert_eq!(err.msg, *msg);
+
+            // streaming tokenizer waits for data
+            let mut tree = ValueTreeBuilder::new();
+            let res = Tokenizer::new_frpc().parse(&data, &mut tree);
+            assert_eq!(res, Ok((true, data.len())));
+        }
+
+        // array of two nulls fits exactly
+        let data = hex::decode("ca1103007058026060").unwrap();
+        assert!(parse_frpc(&data).is_ok());
+    }
+
+    #[test]
+    fn sort_mixed_array() {
+        let mut user = HashMap::new();
+        user.insert("id".to_owned(), Value::Int(1));
+        let mut other = HashMap::new();
+        other.insert("id".to_owned(), Value::Int(2));
+
+        let mut values = vec![
+            Value::Struct(other),
+            Value::Str("b".to_owned()),
+            Value::Double(0.0),
+            Value::Int(3),
+            Value::Struct(user),
+            Value::Null,
+            Value::Array(vec![Value::Int(1)]),
+            Value::Double(-0.0),
+            Value::Str("a".to_owned()),
+            Value::Binary(vec![1]),
+            Value::datetime_from_unix(0),
+            Value::Bool(true),
+            Value::Int(-3),
+            Value::Double(f64::NAN),
+        ];
+        values.sort();
+
+        let sorted: Vec<_> = values.iter().map(|v| v.to_string()).collect();
+        assert_eq!(
+            sorted,
+            vec![
+                "null",
+                "true",
+                "-3",
+                "3",
+                "-0",
+                "0",
+                "NaN",
+                "1970-01-01 00:00:00",
+                "\"a\"",
+                "\"b\"",
+                "b\"01\"",
+                "(1)",
+                "{id: 1}",
+                "{id: 2}",
+            ]
+        );
+
+        assert_eq!(Value::Double(f64::NAN), Value::Double(f64::NAN));
+        assert_ne!(Value::Double(0.0), Value::Double(-0.0));
+        assert!(Value::Array(vec![]) < Value::Array(vec![Value::Null]));
+    }
+
+    #[test]
+    fn datetime_from_calendar() {
+        // 2017-01-27 12:39:19 UTC in -2:00
+        let v = Value::datetime(2017, 1, 27, 10, 39, 19, -8).unwrap();
+        let_extract!(Value::DateTime(dt), &v, unreachable!());
+        assert_eq!(dt.unix_time, 1485520759);
+        assert_eq!((dt.week_day, dt.hour, dt.time_zone), (5, 10, -8));
+        assert_eq!(v.to_string(), "2017-01-27 12:39:19");
+
+        assert_eq!(
+            Value::datetime(2017, 13, 1, 0, 0, 0, 0),
+            Err("month out of range")
+        );
+        assert_eq!(
+            Value::datetime(2019, 2, 29, 0, 0, 0, 0),
+            Err("day out of range")
+        );
+        assert!(Value::datetime(2020, 2, 29, 0, 0, 0, 0).is_ok());
+        assert_eq!(
+            Value::datetime(2017, 1, 1, 0, 0, 0, -128),
+            Err("time zone out of range")
+        );
+        assert_eq!(
+            Value::datetime(2017, 1, 1, 0, 0, 0, 57),
+            Err("time zone out of range")
+        );
+        assert!(Value::datetime(2017, 1, 1, 0, 0, 0, 56).is_ok());
+        assert_eq!(
+            Value::datetime(1599, 12, 31, 0, 0, 0, 0),
+            Err("year out of range")
+        );
+        assert_eq!(
+            Value::datetime(9999, 1, 1, 0, 0, 0, 0),
+            Err("year out of range")
+        );
+        assert!(Value::datetime(1600, 1, 1, 0, 0, 0, 0).is_ok());
+        assert!(Value::datetime(3647, 12, 31, 0, 0, 0, 0).is_ok());
+    }
+
+    #[test]
+    fn limited_to_string() {
+        let mut v = Value::Int(1);
+        for _ in 0..10 {
+            v = Value::Array(vec![v]);
+        }
+        assert_eq!(v.to_string_limited(3, 10, 10), "((((...))))");
+        assert_eq!(v.to_string_limited(100, 10, 10), v.to_string());
+
+        let mut s = HashMap::new();
+        s.insert("a".to_owned(), Value::Binary(vec![1, 2, 3]));
+        s.insert(
+            "b".to_owned(),
+            Value::Array(vec![Value::Int(1), Value::Int(2)]),
+        );
+        s.insert("c".to_owned(), Value::Null);
+        let v = Value::Struct(s);
+        assert_eq!(
+            v.to_string_limited(3, 2, 2),
+            "{a: b\"0102...\", b: (1, 2), ...}"
+        );
+        assert_eq!(v.to_string_limited(3, 1, 2), "{a: b\"0102...\", ...}");
+        assert_eq!(v.to_string_limited(0, 1, 2), "{...}");
+    }
+
+    #[test]
+    fn error_context_snippet() {
+        let data = hex::decode("ca12030068016d").unwrap();
+        let err = parse_frpc(&data).unwrap_err();
+        assert_eq!(err.msg, "Invalid magic expected 0xCA11");
+
+        assert_eq!(error_context(&data, 1, 2), "0: ca 12 03 00\n      ^^");
+        assert_eq!(error_context(&data, 5, 1), "4: 68 01 6d\n      ^^");
+        assert_eq!(error_context(&data, 7, 1), "6: 6d\n      ^^");
+        assert_eq!(
+            error_context(&data, 1, usize::MAX),
+            "0: ca 12 03 00 68 01 6d\n      ^^"
+        );
+    }
+
+    #[test]
+    fn canonical_bytes_of_struct() {
+        let mut a = HashMap::new();
+        let mut b = HashMap::new();
+        for i in 0..20 {
+            a.insert(format!("key{}", i), Value::Int(i));
+            b.insert(format!("key{}", 19 - i), Value::Int(19 - i));
+        }
+        let (a, b) = (Value::Struct(a), Value::Struct(b));
+
+        let bytes = a.canonical_bytes().unwrap();
+        assert_eq!(bytes, b.canonical_bytes().unwrap());
+        // first member is "key0"
+        assert_eq!(&bytes[2..7], b"\x04key0");
+    }
+
+    #[test]
+    fn encode_call_from_tuple() {
+        for &version in &[Version::V30, Version::V21] {
+            let data = encode_call(version, "user.get", (5i64, "name", true)).unwrap();
+            let call = parse_frpc(&data).unwrap();
+            assert_eq!(call.major_version, version.major());
+            assert_eq!(call.what, ParsedStatus::MethodCall("user.get".to_owned()));
+
+            let (id, field, flag): (i64, String, bool) = FromArgs::from_args(call.values).unwrap();
+            assert_eq!((id, field.as_str(), flag), (5, "name", true));
+        }
+
+        assert_eq!(
+            encode_call(Version::V10, "m", (Value::Null,)),
+            Err("null is not supported by protocol 1.0")
+        );
+    }
+
+    #[test]
+    fn transcode_to_v30() {
+        // m(-5, 2017-01-27 12:39:19) in 2.1
+        let data = hex::decode("ca11020168016d40052800773f8b589d4eb62334").unwrap();
+        let v30 = transcode(&data, Version::V30).unwrap();
+        assert_eq!(&v30[..9], &hex::decode("ca11030068016d0809").unwrap()[..]);
+
+        let call = parse_frpc(&v30).unwrap();
+        assert_eq!(call.major_version, 3);
+        assert_eq!(call.values[0], Value::Int(-5));
+        assert_eq!(call.to_string(), "m(-5, 2017-01-27 12:39:19)");
+
+        // null doesn't exist in 1.0
+        let data = hex::decode("ca1103007060").unwrap();
+        assert_eq!(
+            transcode(&data, Version::V10),
+            Err(TranscodeError::Serialize(
+                "null is not supported by protocol 1.0"
+            ))
+        );
+    }
+
+    #[test]
+    fn transcode_to_v10() {
+        // m(-5, 2017-01-27 12:39:19) in 2.1
+        let data = hex::decode("ca11020168016d40052800773f8b589d4eb62334").unwrap();
+        let v10 = transcode(&data, Version::V10).unwrap();
+        let call = parse_frpc(&v10).unwrap();
+        assert_eq!(call.major_version, 1);
+        assert_eq!(call.values, parse_frpc(&data).unwrap().values);
+        assert_eq!(call.to_string(), "m(-5, 2017-01-27 12:39:19)");
+
+        let ints = [-1, -129, 128, i32::MIN as i64, i32::MAX as i64];
+        let args = || ints.iter().map(|&v| Value::Int(v)).collect::<Vec<_>>();
+        let mut data = vec![];
+        Serializer::new()
+            .with_version(Version::V21)
+            .append_call(&mut data, "m", args())
+            .unwrap();
+        let v10 = transcode(&data, Version::V10).unwrap();
+        assert_eq!(parse_frpc(&v10).unwrap().values, args());
+    }
+
+    #[test]
+    fn peek_method_name() {
+        // server.stat((0, 1, ..., 9999)), arguments are not decoded
+        let args: Vec<Value> = (0..10000).map(Value::Int).collect();
+        let args = vec![Value::Array(args)];
+        let mut data = vec![];
+        Serializer::new()
+            .append_call(&mut data, "server.stat", args)
+            .unwrap();
+        assert_eq!(peek_method(&data), Ok(Some("server.stat")));
+        assert_eq!(peek_method(&data[..17]), Ok(Some("server.stat")));
+        assert_eq!(
+            peek_method(&data[..16]).unwrap_err().msg,
+            "unexpected data end"
+        );
+
+        let data = hex::decode("ca1103007020016b").unwrap();
+        assert_eq!(peek_method(&data), Ok(None));
+        let data = hex::decode("ca12030068016d").unwrap();
+        assert_eq!(
+            peek_method(&data).unwrap_err().msg,
+            "Invalid magic expected 0xCA11"
+        );
+    }
 }
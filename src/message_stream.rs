@@ -0,0 +1,145 @@
+use crate::tokenizer::{Tokenizer, TokenizerState};
+use crate::value_tree_builder::{ParsedStatus, ValueTreeBuilder};
+use crate::ParseError;
+
+const MAGIC: [u8; 2] = [0xca, 0x11];
+
+/// Iterator over complete messages concatenated in one slice, e.g. batch of
+/// calls read from file. Stops at the end of data or after first error,
+/// error positions are offsets into whole slice.
+#[derive(Debug)]
+pub struct MessageStream<'a> {
+    data: &'a [u8],
+    pos: usize,
+    tokenizer: Tokenizer,
+    failed: bool,
+}
+
+impl<'a> MessageStream<'a> {
+    /// Stream of FRPC messages
+    pub fn new_frpc(data: &'a [u8]) -> MessageStream<'a> {
+        MessageStream::new(Tokenizer::new_frpc(), data)
+    }
+
+    /// Stream of FRPS messages
+    pub fn new_frps(data: &'a [u8]) -> MessageStream<'a> {
+        MessageStream::new(Tokenizer::new_frps(), data)
+    }
+
+    /// Use configured `tokenizer`, it is reset before every message
+    pub fn new(tokenizer: Tokenizer, data: &'a [u8]) -> MessageStream<'a> {
+        MessageStream {
+            data,
+            pos: 0,
+            tokenizer,
+            failed: false,
+        }
+    }
+
+    /// Offset of first byte not consumed yet
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    fn fail(&mut self, pos: usize, msg: String) -> Option<Result<ValueTreeBuilder, ParseError>> {
+        self.failed = true;
+        Some(Err(ParseError { pos, msg }))
+    }
+}
+
+impl<'a> Iterator for MessageStream<'a> {
+    type Item = Result<ValueTreeBuilder, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.pos == self.data.len() {
+            return None;
+        }
+
+        self.tokenizer.reset();
+        let mut tree = ValueTreeBuilder::new();
+
+        // Call has no end mark, so message is tokenized up to next magic.
+        // Magic inside of value (e.g. string) leaves message incomplete and
+        // tokenizing continues up to the following one.
+        let mut search = self.pos + 1;
+        loop {
+            let end = self.data[search..]
+                .windows(MAGIC.len())
+                .position(|w| w == MAGIC)
+                .map_or(self.data.len(), |i| search + i);
+
+            match self.tokenizer.parse(&self.data[self.pos..end], &mut tree) {
+                Ok((_, processed)) if self.pos + processed < end => {
+                    return self.fail(self.pos + processed, "data after end".to_owned());
+                }
+                Ok((need_data, _)) => {
+                    self.pos = end;
+                    if end == self.data.len() {
+                        if need_data {
+                            return self.fail(end, "unexpected data end".to_owned());
+                        }
+                        return Some(Ok(tree));
+                    }
+                    match self.tokenizer.state() {
+                        TokenizerState::Complete | TokenizerState::ExpectingOptionalArgs => {
+                            return Some(Ok(tree))
+                        }
+                        _ => search = end + 1,
+                    }
+                }
+                Err(pos) => {
+                    let msg = match &tree.what {
+                        ParsedStatus::Error(msg) => msg.clone(),
+                        _ => "invalid data".to_owned(),
+                    };
+                    return self.fail(self.pos + pos, msg);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Value;
+
+    #[test]
+    fn concatenated_responses() {
+        // "a", b"\xca\x11" (magic inside of value) and 5
+        let data = hex::decode("ca11030070200161ca110300703002ca11ca11030070080a").unwrap();
+        let messages: Vec<ValueTreeBuilder> = MessageStream::new_frpc(&data)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let values: Vec<&Value> = messages.iter().map(|m| &m.values[0]).collect();
+        assert_eq!(
+            values,
+            vec![
+                &Value::Str("a".to_owned()),
+                &Value::Binary(vec![0xca, 0x11]),
+                &Value::Int(5)
+            ]
+        );
+
+        // calls m(1) and m()
+        let data = hex::decode("ca11030068016d0802ca11030068016d").unwrap();
+        let calls: Vec<_> = MessageStream::new_frpc(&data).collect();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].as_ref().unwrap().values, vec![Value::Int(1)]);
+        assert!(calls[1].as_ref().unwrap().values.is_empty());
+
+        // stream stops after error
+        let data = hex::decode("ca11030070200161ffca11030070200161").unwrap();
+        let mut stream = MessageStream::new_frpc(&data);
+        let err = stream.next().unwrap().unwrap_err();
+        assert_eq!((err.pos, err.msg.as_str()), (8, "data after end"));
+        assert!(stream.next().is_none());
+
+        // response without value, next magic is read as its value
+        let data = hex::decode("ca11030070ca11030070200161").unwrap();
+        let mut stream = MessageStream::new_frpc(&data);
+        let err = stream.next().unwrap().unwrap_err();
+        assert_eq!((err.pos, err.msg.as_str()), (6, "unknown type 0xca at 5"));
+        assert!(stream.next().is_none());
+    }
+}
@@ -0,0 +1,113 @@
+use crate::datetime::DateTimeVer30;
+use crate::tokenizer::Callback;
+
+/// Callback stopping tokenizer as soon as message type is known, so only
+/// header of call (with method name) is tokenized.
+#[derive(Debug, Default)]
+pub(crate) struct MethodPeek {
+    /// Length of method name when call was tokenized up to its arguments
+    pub name_length: Option<usize>,
+    /// `true` when message is response or fault
+    pub no_call: bool,
+    /// Error reported by tokenizer
+    pub error: Option<String>,
+    // bytes of method name received so far
+    received: usize,
+}
+
+impl MethodPeek {
+    /// `true` when tokenizer was stopped by this callback
+    pub fn stopped(&self) -> bool {
+        self.name_length.is_some() || self.no_call
+    }
+}
+
+impl Callback for MethodPeek {
+    fn error(&mut self, msg: &str) {
+        if !self.stopped() {
+            self.error = Some(msg.to_owned());
+        }
+    }
+
+    fn version(&mut self, _major_version: u8, _minor_version: u8) -> bool {
+        true
+    }
+
+    fn call(&mut self, method: &str, length: usize) -> bool {
+        // name may come in more chunks
+        self.received += method.len();
+        if self.received < length {
+            return true;
+        }
+        self.name_length = Some(length);
+        false
+    }
+
+    fn response(&mut self) -> bool {
+        self.no_call = true;
+        false
+    }
+
+    fn fault(&mut self) -> bool {
+        self.no_call = true;
+        false
+    }
+
+    // tokenizer is stopped before any value
+
+    fn stream_data(&mut self, _v: &[u8]) -> bool {
+        false
+    }
+
+    fn null(&mut self) -> bool {
+        false
+    }
+
+    fn integer(&mut self, _v: i64) -> bool {
+        false
+    }
+
+    fn boolean(&mut self, _v: bool) -> bool {
+        false
+    }
+
+    fn double_number(&mut self, _v: f64) -> bool {
+        false
+    }
+
+    fn datetime(&mut self, _v: DateTimeVer30) -> bool {
+        false
+    }
+
+    fn string_begin(&mut self, _len: usize) -> bool {
+        false
+    }
+
+    fn string_data(&mut self, _v: &[u8], _len: usize) -> bool {
+        false
+    }
+
+    fn binary_begin(&mut self, _len: usize) -> bool {
+        false
+    }
+
+    fn binary_data(&mut self, _v: &[u8], _len: usize) -> bool {
+        false
+    }
+
+    fn array_begin(&mut self, _len: usize) -> bool {
+        false
+    }
+
+    fn struct_begin(&mut self, _len: usize) -> bool {
+        false
+    }
+
+    fn struct_key(&mut self, _v: &[u8], _len: usize) -> bool {
+        false
+    }
+
+    fn value_end(&mut self) -> bool {
+        false
+    }
+}
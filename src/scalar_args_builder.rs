@@ -0,0 +1,202 @@
+use crate::datetime::DateTimeVer30;
+use crate::tokenizer::Callback;
+use crate::Value;
+
+/// Lightweight callback for calls with scalar arguments only (int, string,
+/// binary, bool, double, datetime and null). Arguments are pushed directly
+/// to `values`, arrays and structs stop tokenizing with error.
+#[derive(Debug, Default)]
+pub struct ScalarArgsBuilder {
+    pub method: String,
+    pub values: Vec<Value>,
+    /// Reason why tokenizing stopped
+    pub error: Option<String>,
+
+    // string or binary being received
+    chunks: Vec<u8>,
+}
+
+impl ScalarArgsBuilder {
+    pub fn new() -> ScalarArgsBuilder {
+        ScalarArgsBuilder::default()
+    }
+
+    /// Prepare builder for next call, allocated memory is kept for reuse
+    pub fn reset(&mut self) {
+        self.method.clear();
+        self.values.clear();
+        self.error = None;
+        self.chunks.clear();
+    }
+
+    fn fail(&mut self, msg: &str) -> bool {
+        self.error = Some(msg.to_owned());
+        false
+    }
+}
+
+impl Callback for ScalarArgsBuilder {
+    /// First reported error is kept
+    fn error(&mut self, msg: &str) {
+        if self.error.is_none() {
+            self.error = Some(msg.to_owned());
+        }
+    }
+
+    fn version(&mut self, _major_version: u8, _minor_version: u8) -> bool {
+        true
+    }
+
+    fn call(&mut self, method: &str, _length: usize) -> bool {
+        // name can come in more chunks
+        self.method.push_str(method);
+        true
+    }
+
+    fn response(&mut self) -> bool {
+        self.fail("only call is accepted")
+    }
+
+    fn fault(&mut self) -> bool {
+        self.fail("only call is accepted")
+    }
+
+    fn stream_data(&mut self, _v: &[u8]) -> bool {
+        self.fail("only call is accepted")
+    }
+
+    fn null(&mut self) -> bool {
+        self.values.push(Value::Null);
+        true
+    }
+
+    fn integer(&mut self, v: i64) -> bool {
+        self.values.push(Value::Int(v));
+        true
+    }
+
+    fn boolean(&mut self, v: bool) -> bool {
+        self.values.push(Value::Bool(v));
+        true
+    }
+
+    fn double_number(&mut self, v: f64) -> bool {
+        self.values.push(Value::Double(v));
+        true
+    }
+
+    fn datetime(&mut self, v: DateTimeVer30) -> bool {
+        self.values.push(Value::DateTime(v));
+        true
+    }
+
+    fn string_begin(&mut self, _len: usize) -> bool {
+        self.chunks.clear();
+        self.values.push(Value::Str(String::new()));
+        true
+    }
+
+    fn string_data(&mut self, v: &[u8], _len: usize) -> bool {
+        self.chunks.extend_from_slice(v);
+        true
+    }
+
+    fn binary_begin(&mut self, _len: usize) -> bool {
+        self.chunks.clear();
+        self.values.push(Value::Binary(vec![]));
+        true
+    }
+
+    fn binary_data(&mut self, v: &[u8], _len: usize) -> bool {
+        self.chunks.extend_from_slice(v);
+        true
+    }
+
+    fn array_begin(&mut self, _len: usize) -> bool {
+        self.fail("array is not scalar argument")
+    }
+
+    fn struct_begin(&mut self, _len: usize) -> bool {
+        self.fail("struct is not scalar argument")
+    }
+
+    fn struct_key(&mut self, _v: &[u8], _len: usize) -> bool {
+        self.fail("struct is not scalar argument")
+    }
+
+    /// Only string and binary can end here
+    fn value_end(&mut self) -> bool {
+        let chunks = std::mem::take(&mut self.chunks);
+        match self.values.last_mut() {
+            Some(Value::Str(v)) => match String::from_utf8(chunks) {
+                Ok(s) => *v = s,
+                Err(_) => return self.fail("invalid utf8 string"),
+            },
+            Some(Value::Binary(v)) => *v = chunks,
+            _ => return self.fail("unexpected value end"),
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Tokenizer;
+
+    #[test]
+    fn scalar_call() {
+        // m(1, "ab", true, null)
+        let data = hex::decode("ca11030068016d0802200261621160").unwrap();
+        let mut builder = ScalarArgsBuilder::new();
+        let mut tokenizer = Tokenizer::new_frpc();
+
+        // string is split between chunks
+        assert_eq!(tokenizer.parse(&data[..12], &mut builder), Ok((true, 12)));
+        assert_eq!(
+            tokenizer.parse(&data[12..], &mut builder),
+            Ok((false, data.len() - 12))
+        );
+        assert_eq!(builder.method, "m");
+        assert_eq!(
+            builder.values,
+            vec![
+                Value::Int(1),
+                Value::Str("ab".to_owned()),
+                Value::Bool(true),
+                Value::Null
+            ]
+        );
+        assert_eq!(builder.error, None);
+    }
+
+    #[test]
+    fn nested_array() {
+        // m(1, (2))
+        let data = hex::decode("ca11030068016d080258010804").unwrap();
+        let mut builder = ScalarArgsBuilder::new();
+        let mut tokenizer = Tokenizer::new_frpc();
+        assert!(tokenizer.parse(&data, &mut builder).is_err());
+        assert_eq!(
+            builder.error,
+            Some("array is not scalar argument".to_owned())
+        );
+        assert_eq!(builder.values, vec![Value::Int(1)]);
+    }
+
+    #[test]
+    fn failed_callbacks_set_error() {
+        let mut builder = ScalarArgsBuilder::new();
+        assert!(!builder.struct_key(b"a", 1));
+        assert_eq!(
+            builder.error,
+            Some("struct is not scalar argument".to_owned())
+        );
+
+        // value end without string or binary
+        let mut builder = ScalarArgsBuilder::new();
+        assert!(builder.integer(1));
+        assert!(!builder.value_end());
+        assert_eq!(builder.error, Some("unexpected value end".to_owned()));
+    }
+}
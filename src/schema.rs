@@ -0,0 +1,225 @@
+use crate::Value;
+use std::fmt;
+
+/// Expected kind of single value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Kind {
+    /// Any value is accepted
+    Any,
+    Int,
+    Str,
+    Null,
+    DateTime,
+    Struct(Vec<Field>),
+    /// Array where every item has given kind
+    Array(Box<Kind>),
+    Double,
+    Bool,
+    Binary,
+}
+
+/// Struct member description. Members which are not described are ignored
+/// so a peer can add new members without breaking validation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name: String,
+    pub kind: Kind,
+    /// Member may be missing
+    pub optional: bool,
+}
+
+impl Field {
+    pub fn new(name: &str, kind: Kind) -> Field {
+        Field {
+            name: name.to_owned(),
+            kind,
+            optional: false,
+        }
+    }
+
+    pub fn optional(name: &str, kind: Kind) -> Field {
+        Field {
+            name: name.to_owned(),
+            kind,
+            optional: true,
+        }
+    }
+}
+
+/// Expected kinds of call arguments (or of single response value)
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Schema {
+    pub args: Vec<Kind>,
+}
+
+/// First mismatch found during validation. Path has the same format as in
+/// `Value::walk`, e.g. `[0].user.id`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    pub path: String,
+    pub msg: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.msg)
+        } else {
+            write!(f, "{}: {}", self.path, self.msg)
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl Schema {
+    pub fn new(args: Vec<Kind>) -> Schema {
+        Schema { args }
+    }
+
+    /// Check `values` against schema, report first mismatch
+    pub fn validate(&self, values: &[Value]) -> Result<(), ValidationError> {
+        if values.len() != self.args.len() {
+            return Err(ValidationError {
+                path: String::new(),
+                msg: format!("expected {} values, got {}", self.args.len(), values.len()),
+            });
+        }
+
+        let mut path = String::new();
+        for (i, (kind, v)) in self.args.iter().zip(values).enumerate() {
+            path.push_str(&format!("[{}]", i));
+            Schema::check(kind, v, &mut path)?;
+            path.clear();
+        }
+        Ok(())
+    }
+
+    // recursive implementation
+    fn check(kind: &Kind, val: &Value, path: &mut String) -> Result<(), ValidationError> {
+        let len = path.len();
+        match (kind, val) {
+            (Kind::Any, _)
+            | (Kind::Int, Value::Int(_))
+            | (Kind::Str, Value::Str(_))
+            | (Kind::Null, Value::Null)
+            | (Kind::DateTime, Value::DateTime(_))
+            | (Kind::Double, Value::Double(_))
+            | (Kind::Bool, Value::Bool(_))
+            | (Kind::Binary, Value::Binary(_)) => Ok(()),
+            (Kind::Array(kind), Value::Array(v)) => {
+                for (i, x) in v.iter().enumerate() {
+                    path.push_str(&format!("[{}]", i));
+                    Schema::check(kind, x, path)?;
+                    path.truncate(len);
+                }
+                Ok(())
+            }
+            (Kind::Struct(fields), Value::Struct(v)) => {
+                for field in fields {
+                    if len > 0 {
+                        path.push('.');
+                    }
+                    path.push_str(&field.name);
+                    match v.get(&field.name) {
+                        Some(x) => Schema::check(&field.kind, x, path)?,
+                        None if field.optional => {}
+                        None => {
+                            return Err(ValidationError {
+                                path: path.clone(),
+                                msg: "missing member".to_owned(),
+                            })
+                        }
+                    }
+                    path.truncate(len);
+                }
+                Ok(())
+            }
+            _ => Err(ValidationError {
+                path: path.clone(),
+                msg: format!(
+                    "expected {}, got {}",
+                    Schema::kind_name(kind),
+                    val.type_name()
+                ),
+            }),
+        }
+    }
+
+    fn kind_name(kind: &Kind) -> &'static str {
+        match kind {
+            Kind::Any => "any",
+            Kind::Int => "int",
+            Kind::Str => "string",
+            Kind::Null => "null",
+            Kind::DateTime => "datetime",
+            Kind::Struct(_) => "struct",
+            Kind::Array(_) => "array",
+            Kind::Double => "double",
+            Kind::Bool => "bool",
+            Kind::Binary => "binary",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_frpc;
+
+    fn user_schema() -> Schema {
+        // call(int, [{id: int, name: string, ?tags: [string]}])
+        Schema::new(vec![
+            Kind::Int,
+            Kind::Array(Box::new(Kind::Struct(vec![
+                Field::new("id", Kind::Int),
+                Field::new("name", Kind::Str),
+                Field::optional("tags", Kind::Array(Box::new(Kind::Str))),
+            ]))),
+        ])
+    }
+
+    #[test]
+    fn matching_payload() {
+        // m(1, ({id: 7, name: "x"}))
+        let data = hex::decode("ca11030068016d080258015002026964080e046e616d65200178").unwrap();
+        let call = parse_frpc(&data).unwrap();
+        assert_eq!(call.validate(&user_schema()), Ok(()));
+    }
+
+    #[test]
+    fn mismatching_payload() {
+        // m(1, ({id: "7", name: "x"}))
+        let data = hex::decode("ca11030068016d080258015002026964200137046e616d65200178").unwrap();
+        let call = parse_frpc(&data).unwrap();
+
+        let err = call.validate(&user_schema()).unwrap_err();
+        assert_eq!(err.path, "[1][0].id");
+        assert_eq!(err.to_string(), "[1][0].id: expected int, got string");
+
+        let err = call.validate(&Schema::new(vec![Kind::Int])).unwrap_err();
+        assert_eq!(err.msg, "expected 1 values, got 2");
+    }
+
+    #[test]
+    fn fault_and_error_rejected() {
+        // fault(500, "x") has values matching schema
+        let schema = Schema::new(vec![Kind::Int, Kind::Str]);
+        let data = hex::decode("ca1102017839f401200178").unwrap();
+        let fault = parse_frpc(&data).unwrap();
+        assert_eq!(
+            fault.validate(&schema).unwrap_err().msg,
+            "fault can't be validated"
+        );
+
+        // invalid magic
+        let mut tree = crate::ValueTreeBuilder::new();
+        let data = hex::decode("ca12030068016d").unwrap();
+        let res = crate::Tokenizer::new_frpc().parse(&data, &mut tree);
+        assert!(res.is_err());
+        assert_eq!(
+            tree.validate(&schema).unwrap_err().msg,
+            "message not decoded"
+        );
+    }
+}
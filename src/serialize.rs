@@ -3,9 +3,14 @@ use std::cmp;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::convert::TryInto;
+use std::io::{self, Read, Write};
 use std::{i64, u64};
 
+use crate::args::IntoArgs;
 use crate::common::*;
+use crate::datetime::DateTimeVer30;
+use crate::encoding::zigzag_encode;
+use crate::version::Version;
 use crate::Value;
 
 static ZERO: u64 = 0;
@@ -43,40 +48,17 @@ fn get_octets(number: u64) -> usize {
     7
 }
 
-/** Encodes signed integer as unsigned,
- * with positive values even and negative values odd
- * starting around zero.
- * This saves transfer space and unifies integer encoding.
- * 0 -> 0
- * -1 -> 1
- * 1 -> 2
- * -2 -> 3
- * 2 -> 4
- * ...
- */
-fn zigzag_encode(n: i64) -> u64 {
-    // the right shift has to be arithmetic
-    // negative numbers become all binary 1s
-    // positive numbers become all binary 0s
-    // effectively inverting bits of the result in
-    // case of negative number
-    // let tmp = (n << 1) ^ (n >> 63);
-
-    let tmp = n.overflowing_shl(1).0 ^ n.overflowing_shr(63).0;
-    u64::from_le_bytes(tmp.to_le_bytes())
-}
-
 /** Writes protocol header and message type
  * @return Number of bytes written or zero when there is not enough room
  */
-fn write_magic(msg_type: u8, dst: &mut [u8]) -> Result<usize, &'static str> {
+fn write_magic(version: Version, msg_type: u8, dst: &mut [u8]) -> Result<usize, &'static str> {
     if dst.len() < 5 {
         return Err("not enought space");
     }
     dst[0] = 0xCA;
     dst[1] = 0x11;
-    dst[2] = 3; // FRPC_MAJOR_VERSION
-    dst[3] = 0; // FRPC_MINOR_VERSION
+    dst[2] = version.major();
+    dst[3] = version.minor();
     dst[4] = msg_type;
     Ok(5)
 }
@@ -101,18 +83,76 @@ fn write_null(dst: &mut [u8]) -> Result<usize, &'static str> {
 }
 
 /** Writes tag and integer value */
-fn write_int(val: i64, dst: &mut [u8]) -> Result<usize, &'static str> {
+pub(crate) fn write_int(val: i64, dst: &mut [u8]) -> Result<usize, &'static str> {
+    write_int_width(val, 1, dst)
+}
+
+/** Writes tag and integer value in at least `width` bytes (at most 8) */
+fn write_int_width(val: i64, width: usize, dst: &mut [u8]) -> Result<usize, &'static str> {
     let val = zigzag_encode(val);
-    let octets = get_octets(val);
+    let octets = cmp::max(get_octets(val), width.clamp(1, 8) - 1);
     if dst.len() < (octets + 2) {
         return Err("not enought space");
     }
     dst[0] = INT_ID | u8::try_from(octets).unwrap();
-    LittleEndian::write_u64(&mut dst[1..], val);
+    dst[1..octets + 2].copy_from_slice(&val.to_le_bytes()[..octets + 1]);
+
+    Ok(octets + /*header*/ 1 + /*first byte*/1)
+}
+
+/** Writes tag and integer value for protocol 2.x, sign is stored in tag */
+fn write_int_v2(val: i64, width: usize, dst: &mut [u8]) -> Result<usize, &'static str> {
+    let (id, val) = if val < 0 {
+        (VINT_ID, (val as u64).wrapping_neg())
+    } else {
+        (U_VINT_ID, val as u64)
+    };
+    let octets = cmp::max(get_octets(val), width.clamp(1, 8) - 1);
+    if dst.len() < (octets + 2) {
+        return Err("not enought space");
+    }
+    dst[0] = id | u8::try_from(octets).unwrap();
+    dst[1..octets + 2].copy_from_slice(&val.to_le_bytes()[..octets + 1]);
 
     Ok(octets + /*header*/ 1 + /*first byte*/1)
 }
 
+/** Writes tag and integer value for protocol 1.0 where int has 32 bits */
+fn write_int_v1(val: i64, width: usize, dst: &mut [u8]) -> Result<usize, &'static str> {
+    let val = i32::try_from(val).map_err(|_| "integer out of range of protocol 1.0")?;
+    // octets are count of bytes of two's complement int, so highest bit of
+    // last byte has to be sign
+    let bytes = (1..4)
+        .find(|n| {
+            let bound = 1i32 << (8 * n - 1);
+            -bound <= val && val < bound
+        })
+        .unwrap_or(4);
+    let octets = cmp::max(bytes, width.clamp(1, 4));
+    if dst.len() < (octets + 1) {
+        return Err("not enought space");
+    }
+    dst[0] = INT_ID | u8::try_from(octets).unwrap();
+    dst[1..octets + 1].copy_from_slice(&val.to_le_bytes()[..octets]);
+
+    Ok(octets + /*header*/ 1)
+}
+
+/** Writes tag and integer value in encoding of protocol `version` using at
+ * least `width` bytes */
+fn write_int_version(
+    version: Version,
+    val: i64,
+    width: usize,
+    dst: &mut [u8],
+) -> Result<usize, &'static str> {
+    match version.major() {
+        3 => write_int_width(val, width, dst),
+        2 => write_int_v2(val, width, dst),
+        _ => write_int_v1(val, width, dst),
+    }
+}
+
 /** Writes tag and double value */
 fn write_double(val: f64, dst: &mut [u8]) -> Result<usize, &'static str> {
     if dst.len() < 9 {
@@ -124,51 +164,13 @@ fn write_double(val: f64, dst: &mut [u8]) -> Result<usize, &'static str> {
     Ok(9)
 }
 
-// impl From<DateTime<Utc>> for DateTimeVer30 {
-//     fn from(dt: DateTime<Utc>) -> Self {
-//         DateTimeVer30 {
-//             time_zone: 0,
-//             unix_time: dt.timestamp(),
-//             week_day: match dt.weekday() {
-//                 Weekday::Mon => 1,
-//                 Weekday::Tue => 2,
-//                 Weekday::Wed => 3,
-//                 Weekday::Thu => 4,
-//                 Weekday::Fri => 5,
-//                 Weekday::Sat => 6,
-//                 Weekday::Sun => 0,
-//             },
-//             sec: dt.time().second() as u8,
-//             min: dt.time().minute() as u8,
-//             hour: dt.time().hour() as u8,
-//             day: dt.day() as u8,
-//             month: dt.month() as u8,
-//             year: dt.year() as u16,
-//         }
-//     }
-// }
-
-// impl Into<DateTime<Utc>> for DateTimeVer30 {
-//     fn into(self) -> DateTime<Utc> {
-//         // -1 means unrepresentable time according FRPC protocol specification
-//         if self.unix_time != -1 {
-//             let naive_datetime = NaiveDateTime::from_timestamp(self.unix_time as i64, 0);
-//             return DateTime::<Utc>::from_utc(naive_datetime, Utc);
-//         } else {
-//             let dt = FixedOffset::east((self.time_zone as i32) * 60 * 15)
-//                 .ymd(
-//                     1600 + (self.year as i32),
-//                     self.month as u32,
-//                     self.day as u32,
-//                 )
-//                 .and_hms(self.hour as u32, self.min as u32, self.sec as u32);
-//             return dt.into();
-//         }
-//     }
-// }
+/// Lowest year representable by datetime calendar fields
+pub(crate) const MIN_DATETIME_YEAR: u16 = 1600;
+/// Highest year representable by datetime calendar fields
+pub(crate) const MAX_DATETIME_YEAR: u16 = MIN_DATETIME_YEAR + 0x07ff;
 
 /** Writes tag and datetime value */
-fn write_datetime_v30(val: i64, dst: &mut [u8]) -> Result<usize, &'static str> {
+fn write_datetime_v30(val: &DateTimeVer30, dst: &mut [u8]) -> Result<usize, &'static str> {
     if dst.len() < 15 {
         return Err("not enought space");
     }
@@ -187,39 +189,89 @@ fn write_datetime_v30(val: i64, dst: &mut [u8]) -> Result<usize, &'static str> {
     //     uint16_t year : 11;
     // } __attribute__((packed));
 
-    //let dt = time::PrimitiveDateTime::from_unix_timestamp(*val);
-    let dt = time::OffsetDateTime::from_unix_timestamp(val);
-
-    dst[1] = 0; // we know we are utc :-)
-    LittleEndian::write_i64(&mut dst[2..], val);
-
-    let mut byte: u8 = (dt.second() & 0x1f) << 3;
-    byte |= dt.weekday().number_from_sunday() & 0x07;
-    dst[10] = byte;
-    let mut byte: u8 = (dt.minute() & 0x3f) << 1;
-    byte |= (dt.second() & 0x20) >> 5;
-    byte |= (dt.hour() & 0x01) << 7;
-    dst[11] = byte;
-    let mut byte: u8 = (dt.hour() & 0x1e) >> 1;
-    byte |= (dt.day() & 0x0f) << 4;
-    dst[12] = byte;
-    let mut byte: u8 = (dt.day() & 0x1f) >> 4;
-    byte |= (dt.month() & 0x0f) << 1;
-    let year: u16 = if dt.year() < 1600 {
-        0
-    } else {
-        dt.year() as u16 - 1600
-    };
-    byte |= ((year & 0x07) << 5).to_le_bytes()[1];
-    dst[13] = byte;
-    let byte: u8 = ((year & 0x07f8) >> 3).to_le_bytes()[0];
-    dst[14] = byte;
+    dst[1] = val.time_zone as u8;
+    LittleEndian::write_i64(&mut dst[2..], val.unix_time);
+    write_datetime_fields(val, &mut dst[10..15]);
 
     Ok(15)
 }
 
+/** Writes tag and datetime value for protocol 1.0 and 2.x */
+fn write_datetime_v10(val: &DateTimeVer30, dst: &mut [u8]) -> Result<usize, &'static str> {
+    if dst.len() < 11 {
+        return Err("not enought space");
+    }
+
+    dst[0] = DATETIME_ID;
+
+    // struct DateTimeFormat1_t {
+    //     uint8_t timeZone : 8;
+    //     int32_t unixTime : 32;
+    //     uint8_t weekDay : 3;
+    //     uint8_t sec : 6;
+    //     uint8_t minute : 6;
+    //     uint8_t hour : 5;
+    //     uint8_t day : 5;
+    //     uint8_t month : 4;
+    //     uint16_t year : 11;
+    // } __attribute__((packed));
+
+    dst[1] = val.time_zone as u8;
+    // time not fitting into 32 bits is marked as unrepresentable, peer uses
+    // calendar fields then
+    LittleEndian::write_i32(&mut dst[2..], i32::try_from(val.unix_time).unwrap_or(-1));
+    write_datetime_fields(val, &mut dst[6..11]);
+
+    Ok(11)
+}
+
+/// Writes 5 bytes of packed calendar fields shared by all datetime formats.
+/// Fields are packed from the lowest bit of the first byte like the C bit
+/// fields, spec's "network order" doesn't apply to them.
+fn write_datetime_fields(val: &DateTimeVer30, dst: &mut [u8]) {
+    let mut byte: u8 = (val.sec & 0x1f) << 3;
+    byte |= val.week_day & 0x07;
+    dst[0] = byte;
+    let mut byte: u8 = (val.min & 0x3f) << 1;
+    byte |= (val.sec & 0x20) >> 5;
+    byte |= (val.hour & 0x01) << 7;
+    dst[1] = byte;
+    let mut byte: u8 = (val.hour & 0x1e) >> 1;
+    byte |= (val.day & 0x0f) << 4;
+    dst[2] = byte;
+    let mut byte: u8 = (val.day & 0x1f) >> 4;
+    byte |= (val.month & 0x0f) << 1;
+    // year is 11 bit offset from 1600, calendar fields outside of 1600-3647
+    // are clamped, unix time is still transferred exactly
+    let year = val.year.clamp(MIN_DATETIME_YEAR, MAX_DATETIME_YEAR);
+    if year != val.year {
+        log::warn!("datetime year {} clamped to {}", val.year, year);
+    }
+    let year: u16 = year - MIN_DATETIME_YEAR;
+    byte |= ((year & 0x07) << 5).to_le_bytes()[0];
+    dst[3] = byte;
+    let byte: u8 = ((year & 0x07f8) >> 3).to_le_bytes()[0];
+    dst[4] = byte;
+}
+
+/** Writes tag and datetime value in format of protocol `version` */
+fn write_datetime(
+    version: Version,
+    val: &DateTimeVer30,
+    dst: &mut [u8],
+) -> Result<usize, &'static str> {
+    match version.major() {
+        3 => write_datetime_v30(val, dst),
+        _ => write_datetime_v10(val, dst),
+    }
+}
+
 /// Writes `tag` and `length` for string, binary, array and struct types
-fn write_head(frps_type: u8, size: usize, dst: &mut [u8]) -> Result<usize, &'static str> {
+pub(crate) fn write_head(
+    frps_type: u8,
+    size: usize,
+    dst: &mut [u8],
+) -> Result<usize, &'static str> {
     let octets = get_octets(size.try_into().unwrap());
 
     if dst.len() < (octets + 2) {
@@ -227,39 +279,70 @@ fn write_head(frps_type: u8, size: usize, dst: &mut [u8]) -> Result<usize, &'sta
     }
 
     dst[0] = frps_type | u8::try_from(octets).unwrap();
-    // this works only for little-endian systems
-    LittleEndian::write_u64(&mut dst[1..], size as u64);
+    dst[1..octets + 2].copy_from_slice(&(size as u64).to_le_bytes()[..octets + 1]);
 
     Ok(octets + /*header*/ 1 + /*first byte*/1)
 }
 
+/// Writes `tag` and `length` in encoding of protocol 1.0 where octets field
+/// is count of length bytes
+fn write_head_v1(frps_type: u8, size: usize, dst: &mut [u8]) -> Result<usize, &'static str> {
+    let size = u32::try_from(size).map_err(|_| "length out of range of protocol 1.0")?;
+    let octets = get_octets(size as u64) + 1;
+
+    if dst.len() < (octets + 1) {
+        return Err("not enought space");
+    }
+
+    dst[0] = frps_type | u8::try_from(octets).unwrap();
+    dst[1..octets + 1].copy_from_slice(&size.to_le_bytes()[..octets]);
+
+    Ok(octets + /*header*/ 1)
+}
+
+/// Writes `tag` and `length` in encoding of protocol `version`
+fn write_head_version(
+    version: Version,
+    frps_type: u8,
+    size: usize,
+    dst: &mut [u8],
+) -> Result<usize, &'static str> {
+    match version.major() {
+        1 => write_head_v1(frps_type, size, dst),
+        _ => write_head(frps_type, size, dst),
+    }
+}
+
 /// Writes `tag` and `length` for frps data type
 fn write_data_head(size: usize, dst: &mut [u8]) -> Result<usize, &'static str> {
+    if dst.is_empty() {
+        return Err("not enought space");
+    }
+
     if size == 0 {
+        // zero octets mean no data
+        dst[0] = FRPS_DATA_ID;
         return Ok(/*header*/ 1);
     }
 
     let octets = get_octets(size.try_into().unwrap());
 
-    // data size encoded in octects
+    // data size encoded in octects, `get_octets` returns byte count - 1
     let octects_mask: u8 = match octets {
-        0 | 1 | 2 => 1,
-        3 | 4 => 2,
-        5 | 6 | 7 | 8 => 4,
+        0 | 1 => 1,
+        2 | 3 => 2,
+        4..=7 => 4,
         _ => return Err("data too big"),
     };
 
-    dst[0] = octects_mask;
+    let size_len = data_len_octets(octects_mask).unwrap();
 
-    // this works only for little-endian systems
-    LittleEndian::write_u64(&mut dst[1..], size as u64);
+    if dst.len() < 1 + size_len {
+        return Err("not enought space");
+    }
 
-    let size_len = match octects_mask {
-        1 => 2,
-        2 => 4,
-        4 => 8,
-        _ => return Err("data too big"),
-    };
+    dst[0] = FRPS_DATA_ID | octects_mask;
+    dst[1..1 + size_len].copy_from_slice(&(size as u64).to_le_bytes()[..size_len]);
 
     Ok(/*header*/ 1 + size_len)
 }
@@ -273,6 +356,7 @@ fn write_key_head(size: usize, dst: &mut [u8]) -> Result<usize, &'static str> {
     Ok(1)
 }
 
+#[derive(Debug)]
 enum States<'a> {
     Init,
 
@@ -291,7 +375,11 @@ enum States<'a> {
     StructInit(&'a HashMap<String, Value>),
     StructHead(&'a HashMap<String, Value>),
     StructItem(std::collections::hash_map::Iter<'a, String, Value>),
-    StructItemKey(&'a String),
+    SortedItem(std::vec::IntoIter<(&'a String, &'a Value)>),
+    StructItemKey(&'a str),
+    Member(&'a str, &'a Value),
+    PairsItem(std::slice::Iter<'a, (&'a str, &'a Value)>),
+    MembersItem(std::vec::IntoIter<(&'a str, &'a Value)>),
 
     ArrayInit(&'a Vec<Value>),
     ArrayHead(&'a Vec<Value>),
@@ -306,15 +394,33 @@ enum States<'a> {
     FaultCode,
     FaultMsg,
     FaultMsgData,
+    // detail struct waiting below fault, see `write_fault_with_detail`
+    FaultDetail(&'a Value),
 
     DataHead,
     Data,
+
+    // Bottom of stack, marks message being written by method resumed with
+    // full `dst`. Popped when message is complete.
+    Message(Message),
+}
+
+/// Methods writing values by `write_v`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Message {
+    Value,
+    Response,
+    OkResponse,
+    StructResponse,
+    StructFrom,
+    FaultDetail,
 }
 
 /** Represent either temporary buffer
  *   or keep state how many bytes was copied from value parametr source
  *  (string values arrays eg.)
  */
+#[derive(Debug)]
 struct Source {
     len: usize,
     pos: usize,
@@ -348,9 +454,29 @@ impl Source {
     }
 }
 
+/// Initial space reserved by `append_*` methods
+const APPEND_MIN_SIZE: usize = 64;
+
+/// Status value written by `write_ok_response`
+static STATUS_OK: Value = Value::Int(200);
+
+#[derive(Debug)]
 pub struct Serializer<'a> {
     stack: Vec<States<'a>>,
     source: Source, // colecting buffer
+
+    // envelope struct keys used by `write_ok_response`
+    status_key: &'a str,
+    data_key: &'a str,
+
+    // protocol version of written messages
+    version: Version,
+
+    // minimal count of bytes of integers
+    int_width: usize,
+
+    // write struct members ordered by key
+    sort_keys: bool,
 }
 
 impl<'a> Default for Serializer<'a> {
@@ -368,9 +494,58 @@ impl<'a> Serializer<'a> {
                 pos: 0,
                 buffer: [0; 15],
             },
+            status_key: "status",
+            data_key: "data",
+            version: Version::V30,
+            int_width: 1,
+            sort_keys: false,
         }
     }
 
+    /// Write messages in protocol `version` (3.0 by default). Value types
+    /// not supported by the version are reported as error.
+    pub fn with_version(mut self, version: Version) -> Serializer<'a> {
+        self.version = version;
+        self
+    }
+
+    /// Write integers in at least `width` bytes instead of minimal count,
+    /// for peers expecting fixed width. Width is limited by protocol to 8
+    /// bytes (4 bytes in 1.0).
+    pub fn int_width(mut self, width: usize) -> Serializer<'a> {
+        self.int_width = width;
+        self
+    }
+
+    /// Write struct members ordered by key, so equal values are always
+    /// serialized to the same bytes. Members are written in random order
+    /// by default.
+    pub fn sort_keys(mut self, sort: bool) -> Serializer<'a> {
+        self.sort_keys = sort;
+        self
+    }
+
+    /// Set keys of envelope struct written by `write_ok_response`.
+    /// Default keys are `status` and `data`.
+    pub fn with_envelope_keys(mut self, status_key: &'a str, data_key: &'a str) -> Serializer<'a> {
+        self.status_key = status_key;
+        self.data_key = data_key;
+        self
+    }
+
+    // New serializer with configuration of `self`, for values which don't
+    // live long enough to be kept in `self`
+    fn configured<'b>(&self) -> Serializer<'b>
+    where
+        'a: 'b,
+    {
+        Serializer::new()
+            .with_version(self.version)
+            .int_width(self.int_width)
+            .sort_keys(self.sort_keys)
+            .with_envelope_keys(self.status_key, self.data_key)
+    }
+
     // Set Serializer to initial state. must be used bettween calling
     // writeCall, writeValue, writeResponse
     pub fn reset(&mut self) {
@@ -378,6 +553,73 @@ impl<'a> Serializer<'a> {
         self.stack.push(States::Init);
     }
 
+    /// Return lower bound of bytes needed to finish current message, useful
+    /// to size next `dst`. Rest of partially written value is exact, other
+    /// values are counted by their minimal size and message not started yet
+    /// is 0.
+    pub fn pending_bytes(&self) -> usize {
+        let mut pending = self.source.len - self.source.pos;
+        for state in &self.stack {
+            pending += match state {
+                States::StrHead(x) => x.len(),
+                States::BinHead(x) => x.len(),
+                States::Value(_) => 1,
+                // key length, key and value
+                States::Member(key, _) => 2 + key.len(),
+                States::ArrayItem(iter) => iter.len(),
+                States::StructItem(iter) => 2 * iter.len(),
+                States::SortedItem(iter) => 2 * iter.len(),
+                States::PairsItem(iter) => 2 * iter.len(),
+                States::MembersItem(iter) => 2 * iter.len(),
+                _ => 0,
+            };
+        }
+        pending
+    }
+
+    /// Exact count of bytes of `value` (without message header) written
+    /// with current version and integer width
+    pub fn serialized_size(&self, value: &Value) -> Result<usize, &'static str> {
+        // heads and scalars are written to scratch buffer to get their size
+        let mut buffer = [0u8; 15];
+        let size = match value {
+            Value::Int(x) => write_int_version(self.version, *x, self.int_width, &mut buffer)?,
+            Value::Double(x) => write_double(*x, &mut buffer)?,
+            Value::DateTime(x) => write_datetime(self.version, x, &mut buffer)?,
+            Value::Bool(x) => write_bool(*x, &mut buffer)?,
+            Value::Null => {
+                if self.version == Version::V10 {
+                    return Err("null is not supported by protocol 1.0");
+                }
+                write_null(&mut buffer)?
+            }
+            Value::Str(x) => {
+                write_head_version(self.version, STRING_ID, x.len(), &mut buffer)? + x.len()
+            }
+            Value::Binary(x) => {
+                write_head_version(self.version, BIN_ID, x.len(), &mut buffer)? + x.len()
+            }
+            Value::Array(v) => {
+                let mut size = write_head_version(self.version, ARRAY_ID, v.len(), &mut buffer)?;
+                for x in v {
+                    size += self.serialized_size(x)?;
+                }
+                size
+            }
+            Value::Struct(v) => {
+                let mut size = write_head_version(self.version, STRUCT_ID, v.len(), &mut buffer)?;
+                for (key, x) in v {
+                    if key.len() > 255 {
+                        return Err("Key is too long");
+                    }
+                    size += 1 + key.len() + self.serialized_size(x)?;
+                }
+                size
+            }
+        };
+        Ok(size)
+    }
+
     fn write_v(&mut self, dst: &mut [u8], written: usize) -> Result<usize, &'static str> {
         let mut written = written;
 
@@ -396,6 +638,9 @@ impl<'a> Serializer<'a> {
                         *state = States::StackPop;
                     }
                     Value::Null => {
+                        if self.version == Version::V10 {
+                            return Err("null is not supported by protocol 1.0");
+                        }
                         if written == dst.len() {
                             return Ok(written); // dst buffer is full
                         }
@@ -403,7 +648,12 @@ impl<'a> Serializer<'a> {
                         *state = States::StackPop;
                     }
                     Value::Int(x) => {
-                        let cnt = write_int(*x, &mut self.source.buffer).unwrap();
+                        let cnt = write_int_version(
+                            self.version,
+                            *x,
+                            self.int_width,
+                            &mut self.source.buffer,
+                        )?;
                         self.source.prepare(cnt);
                         *state = States::FlushBuffer;
                     }
@@ -413,7 +663,7 @@ impl<'a> Serializer<'a> {
                         *state = States::FlushBuffer;
                     }
                     Value::DateTime(x) => {
-                        let cnt = write_datetime_v30(*x, &mut self.source.buffer).unwrap();
+                        let cnt = write_datetime(self.version, x, &mut self.source.buffer).unwrap();
                         self.source.prepare(cnt);
                         *state = States::FlushBuffer;
                     }
@@ -429,10 +679,18 @@ impl<'a> Serializer<'a> {
                 States::StackPop => {
                     self.stack.pop();
                 }
+                States::Message(_) => {
+                    self.stack.pop();
+                }
 
                 // String
                 States::StrInit(x) => {
-                    let cnt = write_head(STRING_ID, x.len(), &mut self.source.buffer).unwrap();
+                    let cnt = write_head_version(
+                        self.version,
+                        STRING_ID,
+                        x.len(),
+                        &mut self.source.buffer,
+                    )?;
                     self.source.prepare(cnt);
                     *state = States::StrHead(&x);
                 }
@@ -448,12 +706,16 @@ impl<'a> Serializer<'a> {
                 States::StrValue(x) => {
                     written +=
                         Serializer::copy_next_chunk(dst, written, &mut self.source, x.as_bytes());
+                    if !self.source.is_empty() {
+                        return Ok(written); // dst buffer is full
+                    }
                     *state = States::StackPop;
                 }
 
                 // Binary
                 States::BinInit(x) => {
-                    let cnt = write_head(BIN_ID, x.len(), &mut self.source.buffer).unwrap();
+                    let cnt =
+                        write_head_version(self.version, BIN_ID, x.len(), &mut self.source.buffer)?;
                     self.source.prepare(cnt);
                     *state = States::BinHead(&x);
                 }
@@ -468,12 +730,20 @@ impl<'a> Serializer<'a> {
                 }
                 States::BinValue(x) => {
                     written += Serializer::copy_next_chunk(dst, written, &mut self.source, x);
+                    if !self.source.is_empty() {
+                        return Ok(written); // dst buffer is full
+                    }
                     *state = States::StackPop;
                 }
 
                 // Array
                 States::ArrayInit(v) => {
-                    let cnt = write_head(ARRAY_ID, v.len(), &mut self.source.buffer).unwrap();
+                    let cnt = write_head_version(
+                        self.version,
+                        ARRAY_ID,
+                        v.len(),
+                        &mut self.source.buffer,
+                    )?;
                     self.source.prepare(cnt);
                     *state = States::ArrayHead(&v);
                 }
@@ -491,7 +761,12 @@ impl<'a> Serializer<'a> {
 
                 // Struct
                 States::StructInit(v) => {
-                    let cnt = write_head(STRUCT_ID, v.len(), &mut self.source.buffer).unwrap();
+                    let cnt = write_head_version(
+                        self.version,
+                        STRUCT_ID,
+                        v.len(),
+                        &mut self.source.buffer,
+                    )?;
                     self.source.prepare(cnt);
                     *state = States::StructHead(&v);
                 }
@@ -500,26 +775,46 @@ impl<'a> Serializer<'a> {
                     if !self.source.is_empty() {
                         return Ok(written); // dst buffer is full
                     }
-                    *state = States::StructItem(v.iter());
-                }
-                States::StructItem(iter) => {
-                    match iter.next() {
-                        None => *state = States::StackPop,
-                        Some((key, x)) => {
-                            // check key length
-                            if key.len() > 255 {
-                                return Err("Key is too long");
-                            }
-                            if written == dst.len() {
-                                return Ok(written); // dst buffer is full
-                            }
-                            written += write_key_head(key.len(), &mut dst[written..]).unwrap();
-                            self.source.prepare(key.len());
-
-                            self.stack.push(States::Value(x));
-                            self.stack.push(States::StructItemKey(key));
-                        }
+                    *state = if self.sort_keys {
+                        let mut items: Vec<_> = v.iter().collect();
+                        items.sort_by(|a, b| a.0.cmp(b.0));
+                        States::SortedItem(items.into_iter())
+                    } else {
+                        States::StructItem(v.iter())
+                    };
+                }
+                States::StructItem(iter) => match iter.next() {
+                    None => *state = States::StackPop,
+                    Some((key, x)) => self.stack.push(States::Member(key, x)),
+                },
+                States::SortedItem(iter) => match iter.next() {
+                    None => *state = States::StackPop,
+                    Some((key, x)) => self.stack.push(States::Member(key, x)),
+                },
+                States::PairsItem(iter) => match iter.next() {
+                    None => *state = States::StackPop,
+                    Some((key, x)) => self.stack.push(States::Member(key, x)),
+                },
+                States::MembersItem(iter) => match iter.next() {
+                    None => *state = States::StackPop,
+                    Some((key, x)) => self.stack.push(States::Member(key, x)),
+                },
+                States::Member(key, x) => {
+                    // Protocol requires keys in utf8, `&str` guarantees it.
+                    // Raw byte keys would have to be validated here.
+                    // check key length
+                    if key.len() > 255 {
+                        return Err("Key is too long");
+                    }
+                    if written == dst.len() {
+                        return Ok(written); // dst buffer is full
                     }
+                    written += write_key_head(key.len(), &mut dst[written..]).unwrap();
+                    self.source.prepare(key.len());
+
+                    let (key, x) = (*key, *x);
+                    *state = States::Value(x);
+                    self.stack.push(States::StructItemKey(key));
                 }
                 States::StructItemKey(key) => {
                     written +=
@@ -542,7 +837,14 @@ impl<'a> Serializer<'a> {
         while let Some(state) = self.stack.last_mut() {
             match state {
                 States::Init => {
-                    let cnt = write_magic(CALL_ID, &mut self.source.buffer).unwrap();
+                    // invalid name, reject it before anything is written
+                    if name.is_empty() {
+                        return Err("empty method name");
+                    }
+                    if name.len() > 255 {
+                        return Err("method name too long");
+                    }
+                    let cnt = write_magic(self.version, CALL_ID, &mut self.source.buffer).unwrap();
                     self.source.prepare(cnt);
                     *state = States::CallHead;
                 }
@@ -551,9 +853,6 @@ impl<'a> Serializer<'a> {
                     if !self.source.is_empty() {
                         return Ok(written);
                     }
-                    if name.len() > 255 {
-                        return Err("method name too long");
-                    }
                     if written == dst.len() {
                         return Ok(written);
                     }
@@ -571,29 +870,241 @@ impl<'a> Serializer<'a> {
                         &mut self.source,
                         &name.as_bytes(),
                     );
+                    if !self.source.is_empty() {
+                        return Ok(written); // dst buffer is full
+                    }
                     *state = States::StackPop;
                 }
                 States::StackPop => {
                     self.stack.pop();
                 }
+                // arguments from write_call_with_args follow method name
+                States::ArrayItem(_) => return self.write_v(dst, written),
                 _ => return Err("Invalid state"),
             }
         }
         Ok(written)
     }
 
+    /// Write method call together with its arguments. Can be called
+    /// repeatedly with new `dst` until whole call is written.
+    pub fn write_call_with_args(
+        &mut self,
+        dst: &mut [u8],
+        name: &str,
+        args: &'a [Value],
+    ) -> Result<usize, &'static str> {
+        match self.stack.last() {
+            Some(States::Init) => {
+                if name.is_empty() {
+                    return Err("empty method name");
+                }
+                if name.len() > 255 {
+                    return Err("method name too long");
+                }
+                // arguments are written as array items without array head
+                self.stack.insert(0, States::ArrayItem(args.iter()));
+                self.write_call(dst, name)
+            }
+            Some(States::CallHead) | Some(States::CallMethod) => self.write_call(dst, name),
+            Some(_) => self.write_v(dst, 0),
+            None => Err("serializer is not initialized"),
+        }
+    }
+
+    /// Write binary value of `total_len` bytes read from `src` without
+    /// holding whole payload in memory. Can be used in place of any value,
+    /// return number of bytes written to `dst`.
+    pub fn write_binary_stream<W: Write, R: Read>(
+        &self,
+        mut dst: W,
+        total_len: usize,
+        src: R,
+    ) -> io::Result<usize> {
+        let mut head = [0u8; 9];
+        let cnt = write_head_version(self.version, BIN_ID, total_len, &mut head)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        dst.write_all(&head[..cnt])?;
+
+        let copied = io::copy(&mut src.take(total_len as u64), &mut dst)?;
+        if copied != total_len as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "binary source is shorter than declared length",
+            ));
+        }
+        Ok(cnt + total_len)
+    }
+
+    /// Write complete method call with `args` converted to values, e.g.
+    /// `serializer.call(&mut buffer, "user.get", (5, "name"))`. Whole call
+    /// has to fit into `dst`, serializer is reset afterwards.
+    pub fn call<A: IntoArgs>(
+        &mut self,
+        dst: &mut [u8],
+        name: &str,
+        args: A,
+    ) -> Result<usize, &'static str> {
+        // converted arguments don't live long enough to be kept in `self`
+        let args = args.into_args();
+        let mut serializer = self.configured();
+        let written = serializer.write_call_with_args(dst, name, &args)?;
+        self.reset();
+
+        if !serializer.stack.is_empty() {
+            return Err("not enought space");
+        }
+        Ok(written)
+    }
+
+    /// Append complete method call with `args` to `dst`, vector grows as
+    /// needed. Return count of appended bytes.
+    pub fn append_call<A: IntoArgs>(
+        &mut self,
+        dst: &mut Vec<u8>,
+        name: &str,
+        args: A,
+    ) -> Result<usize, &'static str> {
+        // converted arguments don't live long enough to be kept in `self`
+        let args = args.into_args();
+        let mut serializer = self.configured();
+        self.reset();
+        serializer.append_with(dst, |s, dst| s.write_call_with_args(dst, name, &args))
+    }
+
+    /// Append complete response to `dst`, vector grows as needed. Return
+    /// count of appended bytes.
+    pub fn append_response(
+        &mut self,
+        dst: &mut Vec<u8>,
+        value: &'a Value,
+    ) -> Result<usize, &'static str> {
+        self.append_with(dst, |s, dst| s.write_response(dst, value))
+    }
+
+    /// Serialize complete response into vector allocated once with exact
+    /// size given by `serialized_size`. Serializer is reset afterwards.
+    pub fn to_exact_vec(&mut self, value: &'a Value) -> Result<Vec<u8>, &'static str> {
+        let mut dst = vec![0; /*header*/ 5 + self.serialized_size(value)?];
+        self.reset();
+        let res = self.write_response(&mut dst, value);
+        let complete = self.stack.is_empty();
+        self.reset();
+
+        let cnt = res?;
+        debug_assert!(complete && cnt == dst.len(), "size mismatch");
+        Ok(dst)
+    }
+
+    /// Append single value without message header to `dst`, vector grows as
+    /// needed. Return count of appended bytes.
+    pub fn append_value(
+        &mut self,
+        dst: &mut Vec<u8>,
+        value: &'a Value,
+    ) -> Result<usize, &'static str> {
+        self.append_with(dst, |s, dst| s.write_value(dst, value))
+    }
+
+    /// Append complete fault to `dst`, vector grows as needed. Return count
+    /// of appended bytes.
+    pub fn append_fault(
+        &mut self,
+        dst: &mut Vec<u8>,
+        code: i64,
+        msg: &str,
+    ) -> Result<usize, &'static str> {
+        self.append_with(dst, |s, dst| s.write_fault(dst, code, msg))
+    }
+
+    // Write whole message by `write` after end of `dst`. When it doesn't fit
+    // it is written again into twice as large space, so serializer is never
+    // resumed. Serializer is reset afterwards.
+    fn append_with<F>(&mut self, dst: &mut Vec<u8>, mut write: F) -> Result<usize, &'static str>
+    where
+        F: FnMut(&mut Serializer<'a>, &mut [u8]) -> Result<usize, &'static str>,
+    {
+        let start = dst.len();
+        let mut size = cmp::max(APPEND_MIN_SIZE, dst.capacity() - start);
+        loop {
+            dst.resize(start + size, 0);
+            self.reset();
+            let res = write(self, &mut dst[start..]);
+            self.reset();
+            match res {
+                // filled space may mean that message didn't fit
+                Ok(cnt) if cnt < size => {
+                    dst.truncate(start + cnt);
+                    return Ok(cnt);
+                }
+                Ok(_) => size *= 2,
+                Err(e) => {
+                    dst.truncate(start);
+                    return Err(e);
+                }
+            }
+        }
+    }
+
     // create FRPC/S method call with method name.
     pub fn write_value(&mut self, dst: &mut [u8], value: &'a Value) -> Result<usize, &'static str> {
         while let Some(state) = self.stack.last_mut() {
             match state {
-                States::Init => *state = States::Value(&value),
-                States::Value(_) => return self.write_v(dst, 0),
-                _ => return Err("Invalid state"),
+                States::Init => {
+                    *state = States::Message(Message::Value);
+                    self.stack.push(States::Value(&value));
+                }
+                _ => return self.resume(Message::Value, dst, 0),
             }
         }
         Err("serializer is not initialized")
     }
 
+    // Continue writing of `message` started by previous call with full `dst`
+    fn resume(
+        &mut self,
+        message: Message,
+        dst: &mut [u8],
+        written: usize,
+    ) -> Result<usize, &'static str> {
+        match self.stack.first() {
+            Some(States::Message(m)) if *m == message => self.write_v(dst, written),
+            _ => Err("Invalid state"),
+        }
+    }
+
+    /// Write struct value with `len` members taken in order from `items`,
+    /// without building `HashMap`. Iterator is consumed by first call,
+    /// following calls (when `dst` was full) ignore it.
+    pub fn write_struct_from<I>(
+        &mut self,
+        dst: &mut [u8],
+        len: usize,
+        items: I,
+    ) -> Result<usize, &'static str>
+    where
+        I: IntoIterator<Item = (&'a str, &'a Value)>,
+    {
+        match self.stack.last_mut() {
+            Some(state @ States::Init) => {
+                let members: Vec<_> = items.into_iter().collect();
+                if members.len() != len {
+                    return Err("struct length mismatch");
+                }
+                let cnt =
+                    write_head_version(self.version, STRUCT_ID, len, &mut self.source.buffer)?;
+                self.source.prepare(cnt);
+
+                *state = States::Message(Message::StructFrom);
+                self.stack.push(States::MembersItem(members.into_iter()));
+                self.stack.push(States::FlushBuffer);
+                self.write_v(dst, 0)
+            }
+            Some(_) => self.resume(Message::StructFrom, dst, 0),
+            None => Err("serializer is not initialized"),
+        }
+    }
+
     pub fn write_response(
         &mut self,
         dst: &mut [u8],
@@ -604,9 +1115,11 @@ impl<'a> Serializer<'a> {
         while let Some(state) = self.stack.last_mut() {
             match state {
                 States::Init => {
-                    let cnt = write_magic(RESPOSE_ID, &mut self.source.buffer).unwrap();
+                    let cnt =
+                        write_magic(self.version, RESPOSE_ID, &mut self.source.buffer).unwrap();
                     self.source.prepare(cnt);
-                    *state = States::ResponseHead;
+                    *state = States::Message(Message::Response);
+                    self.stack.push(States::ResponseHead);
                 }
                 States::ResponseHead => {
                     written += self.source.flush(dst, written);
@@ -615,8 +1128,73 @@ impl<'a> Serializer<'a> {
                     }
                     *state = States::Value(&value);
                 }
-                States::Value(_) => return self.write_v(dst, written),
-                _ => return Err("Invalid state"),
+                _ => return self.resume(Message::Response, dst, written),
+            }
+        }
+        Err("serializer is not initialized")
+    }
+
+    /// Write response with `data` wrapped into envelope struct
+    /// `{status: 200, data: data}`. Keys can be changed by `with_envelope_keys`.
+    pub fn write_ok_response(
+        &mut self,
+        dst: &mut [u8],
+        data: &'a Value,
+    ) -> Result<usize, &'static str> {
+        while let Some(state) = self.stack.last_mut() {
+            match state {
+                States::Init => {
+                    // response header and envelope struct head fit into buffer
+                    let cnt =
+                        write_magic(self.version, RESPOSE_ID, &mut self.source.buffer).unwrap();
+                    let cnt = cnt
+                        + write_head_version(
+                            self.version,
+                            STRUCT_ID,
+                            2,
+                            &mut self.source.buffer[cnt..],
+                        )
+                        .unwrap();
+                    self.source.prepare(cnt);
+
+                    *state = States::Message(Message::OkResponse);
+                    self.stack.push(States::Member(self.data_key, data));
+                    self.stack.push(States::Member(self.status_key, &STATUS_OK));
+                    self.stack.push(States::FlushBuffer);
+                }
+                _ => return self.resume(Message::OkResponse, dst, 0),
+            }
+        }
+        Err("serializer is not initialized")
+    }
+
+    /// Write response with struct value built from `pairs`. Members are
+    /// written in the order of `pairs`, keys longer than 255 bytes are
+    /// rejected.
+    pub fn write_struct_response(
+        &mut self,
+        dst: &mut [u8],
+        pairs: &'a [(&'a str, &'a Value)],
+    ) -> Result<usize, &'static str> {
+        while let Some(state) = self.stack.last_mut() {
+            match state {
+                States::Init => {
+                    let cnt =
+                        write_magic(self.version, RESPOSE_ID, &mut self.source.buffer).unwrap();
+                    let cnt = cnt
+                        + write_head_version(
+                            self.version,
+                            STRUCT_ID,
+                            pairs.len(),
+                            &mut self.source.buffer[cnt..],
+                        )?;
+                    self.source.prepare(cnt);
+
+                    *state = States::Message(Message::StructResponse);
+                    self.stack.push(States::PairsItem(pairs.iter()));
+                    self.stack.push(States::FlushBuffer);
+                }
+                _ => return self.resume(Message::StructResponse, dst, 0),
             }
         }
         Err("serializer is not initialized")
@@ -634,7 +1212,8 @@ impl<'a> Serializer<'a> {
             match state {
                 States::Init => {
                     // Write response header
-                    let cnt = write_magic(FAULT_RESPOSE_ID, &mut self.source.buffer).unwrap();
+                    let cnt = write_magic(self.version, FAULT_RESPOSE_ID, &mut self.source.buffer)
+                        .unwrap();
                     self.source.prepare(cnt);
                     *state = States::FaultHead;
                 }
@@ -644,7 +1223,12 @@ impl<'a> Serializer<'a> {
                         return Ok(written);
                     }
                     // push status code into the buffer
-                    let cnt = write_int(code, &mut self.source.buffer).unwrap();
+                    let cnt = write_int_version(
+                        self.version,
+                        code,
+                        self.int_width,
+                        &mut self.source.buffer,
+                    )?;
                     self.source.prepare(cnt);
 
                     *state = States::FaultCode;
@@ -656,7 +1240,12 @@ impl<'a> Serializer<'a> {
                     }
 
                     // push status message head into the buffer
-                    let cnt = write_head(STRING_ID, msg.len(), &mut self.source.buffer).unwrap();
+                    let cnt = write_head_version(
+                        self.version,
+                        STRING_ID,
+                        msg.len(),
+                        &mut self.source.buffer,
+                    )?;
                     self.source.prepare(cnt);
 
                     *state = States::FaultMsg;
@@ -688,19 +1277,52 @@ impl<'a> Serializer<'a> {
                 States::StackPop => {
                     self.stack.pop();
                 }
+                // fault is written, detail follows
+                States::FaultDetail(_) => return Ok(written),
                 _ => return Err("Invalid state"),
             }
         }
         Ok(written)
     }
 
+    /// Write fault followed by `detail` struct. This is FRPS only
+    /// extension, FRPC peers reject data after fault message.
+    pub fn write_fault_with_detail(
+        &mut self,
+        dst: &mut [u8],
+        code: i64,
+        msg: &str,
+        detail: &'a Value,
+    ) -> Result<usize, &'static str> {
+        if let [States::Init] = self.stack[..] {
+            if !matches!(detail, Value::Struct(_)) {
+                return Err("fault detail must be struct");
+            }
+            self.stack[0] = States::FaultDetail(detail);
+            self.stack.push(States::Init);
+        }
+
+        let mut written = 0;
+        if let Some(States::FaultDetail(_)) = self.stack.first() {
+            written = self.write_fault(dst, code, msg)?;
+            match self.stack[..] {
+                [States::FaultDetail(detail)] => {
+                    self.stack[0] = States::Message(Message::FaultDetail);
+                    self.stack.push(States::Value(detail));
+                }
+                _ => return Ok(written), // dst buffer is full
+            }
+        }
+        self.resume(Message::FaultDetail, dst, written)
+    }
+
     pub fn write_data(&mut self, dst: &mut [u8], src: &[u8]) -> Result<usize, &'static str> {
         let mut written: usize = 0;
         while let Some(state) = self.stack.last_mut() {
             match state {
                 States::Init => {
                     // Write response header
-                    let cnt = write_data_head(src.len(), &mut self.source.buffer).unwrap();
+                    let cnt = write_data_head(src.len(), &mut self.source.buffer)?;
                     self.source.prepare(cnt);
                     *state = States::DataHead;
                 }
@@ -753,13 +1375,15 @@ impl<'a> Serializer<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{ParsedStatus, Tokenizer, TokenizerState, ValueTreeBuilder};
+    use enum_extract::let_extract;
 
     #[test]
     fn wire_format() {
         let mut buffer: [u8; 256] = [0; 256];
 
         // magic
-        let cnt = write_magic(1u8, &mut buffer).unwrap();
+        let cnt = write_magic(Version::V30, 1u8, &mut buffer).unwrap();
         assert_eq!(cnt, 5);
 
         // bool
@@ -780,11 +1404,13 @@ mod tests {
 
         // Datetime
         let now = time::OffsetDateTime::now();
-        let cnt = write_datetime_v30(now.timestamp(), &mut buffer[cnt..]).unwrap();
+        let dt = DateTimeVer30::from_unix(now.timestamp(), 0);
+        let cnt = write_datetime_v30(&dt, &mut buffer[cnt..]).unwrap();
         assert_eq!(cnt, 15);
 
         // write time before unix epoch
-        let cnt = write_datetime_v30(-now.timestamp(), &mut buffer[cnt..]).unwrap();
+        let dt = DateTimeVer30::from_unix(-now.timestamp(), 0);
+        let cnt = write_datetime_v30(&dt, &mut buffer[cnt..]).unwrap();
         assert_eq!(cnt, 15);
     }
 
@@ -841,4 +1467,706 @@ mod tests {
         _written += cnt.unwrap();
         //println!("Serialized data len: {}", written);
     }
+
+    fn parse_response(data: &[u8]) -> Value {
+        let mut tokenizer = Tokenizer::new_frpc();
+        let mut tree = ValueTreeBuilder::new();
+        assert_eq!(tokenizer.parse(data, &mut tree), Ok((false, data.len())));
+        assert_eq!(tree.what, ParsedStatus::Response);
+        tree.values.pop().unwrap()
+    }
+
+    #[test]
+    fn ok_response_envelope() {
+        let data = Value::Array(vec![Value::Int(1), Value::Str("two".into())]);
+        let mut buffer: [u8; 256] = [0; 256];
+
+        let mut serializer = Serializer::new();
+        let cnt = serializer.write_ok_response(&mut buffer, &data).unwrap();
+
+        let mut expected = HashMap::new();
+        expected.insert("status".to_owned(), Value::Int(200));
+        expected.insert(
+            "data".to_owned(),
+            Value::Array(vec![Value::Int(1), Value::Str("two".into())]),
+        );
+        assert_eq!(parse_response(&buffer[..cnt]), Value::Struct(expected));
+
+        // custom keys
+        let mut serializer = Serializer::new().with_envelope_keys("code", "result");
+        let cnt = serializer.write_ok_response(&mut buffer, &data).unwrap();
+
+        let envelope = parse_response(&buffer[..cnt]);
+        let_extract!(Value::Struct(envelope), envelope, unreachable!());
+        assert_eq!(envelope.get("code"), Some(&Value::Int(200)));
+        assert_eq!(envelope.get("result"), Some(&data));
+    }
+
+    #[test]
+    fn struct_response() {
+        let id = Value::Int(5);
+        let name = Value::Str("name".into());
+        let pairs = [("id", &id), ("name", &name)];
+        let mut buffer: [u8; 256] = [0; 256];
+
+        let mut serializer = Serializer::new();
+        let cnt = serializer
+            .write_struct_response(&mut buffer, &pairs)
+            .unwrap();
+
+        let mut expected = HashMap::new();
+        expected.insert("id".to_owned(), Value::Int(5));
+        expected.insert("name".to_owned(), Value::Str("name".into()));
+        assert_eq!(parse_response(&buffer[..cnt]), Value::Struct(expected));
+    }
+
+    #[test]
+    fn struct_from_iterator() {
+        let members = vec![
+            ("zeta", Value::Int(1)),
+            ("alpha", Value::Str("a".into())),
+            ("mid", Value::Array(vec![Value::Bool(true)])),
+        ];
+        let pairs: Vec<(&str, &Value)> = members.iter().map(|(k, v)| (*k, v)).collect();
+
+        // small buffer forces resuming in the middle of members
+        let mut data = hex::decode("ca11030070").unwrap();
+        let mut serializer = Serializer::new();
+        let mut items = Some(members.iter().map(|(k, v)| (*k, v)));
+        loop {
+            let mut buffer = [0u8; 4];
+            let cnt = serializer
+                .write_struct_from(
+                    &mut buffer,
+                    members.len(),
+                    items.take().into_iter().flatten(),
+                )
+                .unwrap();
+            data.extend_from_slice(&buffer[..cnt]);
+            if cnt < buffer.len() {
+                break;
+            }
+        }
+
+        // members are written in given order
+        let mut buffer = [0u8; 64];
+        let cnt = Serializer::new()
+            .write_struct_response(&mut buffer, &pairs)
+            .unwrap();
+        assert_eq!(data, &buffer[..cnt]);
+
+        let mut serializer = Serializer::new();
+        assert_eq!(
+            serializer.write_struct_from(&mut buffer, 2, pairs.into_iter()),
+            Err("struct length mismatch")
+        );
+
+        // other message can't be continued
+        let mut serializer = Serializer::new();
+        assert_eq!(
+            serializer.write_value(&mut buffer[..2], &members[1].1),
+            Ok(2)
+        );
+        assert_eq!(
+            serializer.write_struct_from(&mut buffer, 0, vec![]),
+            Err("Invalid state")
+        );
+
+        let expected = members
+            .into_iter()
+            .map(|(k, v)| (k.to_owned(), v))
+            .collect();
+        assert_eq!(parse_response(&data), Value::Struct(expected));
+    }
+
+    #[test]
+    fn string_bytes_round_trip() {
+        // control characters, 2, 3 and 4 bytes long codepoints
+        let text = "\u{0}\t\r\n\u{1b}[0m\u{7f} žluťoučký € 😀";
+        let value = Value::Str(text.to_owned());
+        for version in &[Version::V10, Version::V21, Version::V30] {
+            let data = Serializer::new()
+                .with_version(*version)
+                .to_exact_vec(&value)
+                .unwrap();
+            assert_eq!(&data[data.len() - text.len()..], text.as_bytes());
+
+            let tree = crate::parse_frpc(&data).unwrap();
+            let_extract!(Value::Str(v), &tree.values[0], unreachable!());
+            assert_eq!(v.as_bytes(), text.as_bytes());
+        }
+    }
+
+    #[test]
+    fn empty_values_round_trip() {
+        let values = vec![
+            Value::Str(String::new()),
+            Value::Binary(vec![]),
+            Value::Array(vec![]),
+            Value::Struct(HashMap::new()),
+        ];
+        for version in &[Version::V10, Version::V20, Version::V21, Version::V30] {
+            let mut serializer = Serializer::new().with_version(*version);
+            for value in &values {
+                let data = serializer.to_exact_vec(value).unwrap();
+                // tag and one byte of zero length
+                assert_eq!(data.len(), 5 + 2, "{} {:?}", version, value);
+                assert_eq!(data[6], 0);
+
+                // whole message and byte by byte
+                assert_eq!(&crate::parse_frpc(&data).unwrap().values[0], value);
+                let mut tokenizer = Tokenizer::new_frpc();
+                let mut tree = ValueTreeBuilder::new();
+                for byte in &data {
+                    assert!(tokenizer.parse(&[*byte], &mut tree).is_ok());
+                }
+                assert_eq!(tokenizer.state(), TokenizerState::Complete);
+                assert_eq!(&tree.values[0], value);
+            }
+        }
+    }
+
+    #[test]
+    fn empty_method_name() {
+        let mut buffer = [0u8; 16];
+        let args = vec![Value::Int(1)];
+
+        let mut serializer = Serializer::new();
+        assert_eq!(
+            serializer.write_call(&mut buffer, ""),
+            Err("empty method name")
+        );
+        assert_eq!(
+            serializer.write_call_with_args(&mut buffer, "", &args),
+            Err("empty method name")
+        );
+        assert_eq!(
+            serializer.call(&mut buffer, "", (1,)),
+            Err("empty method name")
+        );
+        assert_eq!(buffer, [0u8; 16]);
+
+        // serializer is still usable
+        let cnt = serializer.write_call(&mut buffer, "m").unwrap();
+        assert_eq!(&buffer[..cnt], &[0xca, 0x11, 0x03, 0x00, 0x68, 0x01, b'm']);
+    }
+
+    #[test]
+    fn longest_method_name() {
+        let mut buffer = [0u8; 300];
+        let args = vec![Value::Int(1)];
+
+        let name = "m".repeat(255);
+        let mut serializer = Serializer::new();
+        let cnt = serializer
+            .write_call_with_args(&mut buffer, &name, &args)
+            .unwrap();
+        assert_eq!(buffer[5], 255);
+        let call = crate::parse_frpc(&buffer[..cnt]).unwrap();
+        assert_eq!(call.what, ParsedStatus::MethodCall(name));
+        assert_eq!(call.values, args);
+
+        let name = "m".repeat(256);
+        let mut serializer = Serializer::new();
+        assert_eq!(
+            serializer.write_call(&mut buffer, &name),
+            Err("method name too long")
+        );
+        assert_eq!(
+            serializer.write_call_with_args(&mut buffer, &name, &args),
+            Err("method name too long")
+        );
+
+        // serializer is still usable
+        let cnt = serializer.write_call(&mut buffer, "m").unwrap();
+        assert_eq!(&buffer[..cnt], &[0xca, 0x11, 0x03, 0x00, 0x68, 0x01, b'm']);
+    }
+
+    #[test]
+    fn bool_round_trip() {
+        assert_eq!(BOOL_ID, 0b0001_0000);
+
+        let mut buffer = [0u8; 1];
+        for &(v, tag) in &[(false, 0x10), (true, 0x11)] {
+            assert_eq!(write_bool(v, &mut buffer), Ok(1));
+            assert_eq!(buffer[0], tag);
+
+            let data = [0xca, 0x11, 0x03, 0x00, RESPOSE_ID, tag];
+            assert_eq!(parse_response(&data), Value::Bool(v));
+        }
+
+        // all other additional info values are rejected
+        for info in 2..8 {
+            let data = [0xca, 0x11, 0x03, 0x00, RESPOSE_ID, BOOL_ID | info];
+            assert!(crate::parse_frpc(&data).is_err());
+        }
+    }
+
+    #[test]
+    fn resume_other_message() {
+        let value = Value::Str("x".repeat(100));
+        let args = vec![Value::Str("x".repeat(100))];
+        let mut buffer = [0u8; 16];
+
+        // call is half written
+        let mut serializer = Serializer::new();
+        assert_eq!(
+            serializer.write_call_with_args(&mut buffer, "m", &args),
+            Ok(16)
+        );
+        assert_eq!(
+            serializer.write_response(&mut buffer, &value),
+            Err("Invalid state")
+        );
+        assert_eq!(
+            serializer.write_value(&mut buffer, &value),
+            Err("Invalid state")
+        );
+
+        // response is half written
+        let mut serializer = Serializer::new();
+        assert_eq!(serializer.write_response(&mut buffer, &value), Ok(16));
+        assert_eq!(
+            serializer.write_value(&mut buffer, &value),
+            Err("Invalid state")
+        );
+        assert_eq!(serializer.write_response(&mut buffer, &value), Ok(16));
+    }
+
+    #[test]
+    fn pending_bytes_of_string() {
+        let value = Value::Str("x".repeat(100));
+        let mut serializer = Serializer::new();
+        let mut data = vec![];
+        let mut buffer = [0u8; 7];
+
+        let mut pending = usize::MAX;
+        loop {
+            let cnt = serializer.write_response(&mut buffer, &value).unwrap();
+            data.extend_from_slice(&buffer[..cnt]);
+
+            assert!(serializer.pending_bytes() < pending);
+            pending = serializer.pending_bytes();
+            if cnt < buffer.len() {
+                break;
+            }
+            assert!(pending >= 100 + 7 - data.len());
+        }
+        assert_eq!(pending, 0);
+        assert_eq!(data.len(), 5 + 2 + 100);
+        assert_eq!(parse_response(&data), value);
+    }
+
+    #[test]
+    fn call_with_int_width() {
+        let args = vec![Value::Int(1)];
+        let mut expected = [0u8; 16];
+        let cnt = Serializer::new()
+            .int_width(4)
+            .write_call_with_args(&mut expected, "m", &args)
+            .unwrap();
+
+        let mut buffer = [0u8; 16];
+        let mut serializer = Serializer::new().int_width(4);
+        assert_eq!(serializer.call(&mut buffer, "m", (1,)), Ok(cnt));
+        assert_eq!(buffer, expected);
+    }
+
+    #[test]
+    fn call_keeps_configuration() {
+        // members are written in random order unless keys are sorted
+        let args = || {
+            let members = ["c", "a", "b", "e", "d"]
+                .iter()
+                .map(|key| (key.to_string(), Value::Int(1)))
+                .collect();
+            vec![Value::Struct(members)]
+        };
+
+        let mut expected = [0u8; 64];
+        let cnt = Serializer::new()
+            .sort_keys(true)
+            .int_width(4)
+            .write_call_with_args(&mut expected, "m", &args())
+            .unwrap();
+
+        let mut serializer = Serializer::new().sort_keys(true).int_width(4);
+        let mut buffer = [0u8; 64];
+        assert_eq!(serializer.call(&mut buffer, "m", args()), Ok(cnt));
+        assert_eq!(buffer, expected);
+
+        let mut data = vec![];
+        assert_eq!(serializer.append_call(&mut data, "m", args()), Ok(cnt));
+        assert_eq!(data, &expected[..cnt]);
+    }
+
+    #[test]
+    fn append_messages() {
+        let long = "x".repeat(300);
+        let value = Value::Str(long.clone());
+        let mut data = vec![];
+
+        let mut serializer = Serializer::new();
+        let call_len = serializer
+            .append_call(&mut data, "test", (1, long.as_str()))
+            .unwrap();
+        assert_eq!(call_len, data.len());
+        let response_len = serializer.append_response(&mut data, &value).unwrap();
+        assert_eq!(call_len + response_len, data.len());
+
+        let call = crate::parse_frpc(&data[..call_len]).unwrap();
+        assert_eq!(call.what, ParsedStatus::MethodCall("test".to_owned()));
+        assert_eq!(call.values, vec![Value::Int(1), value]);
+        let response = crate::parse_frpc(&data[call_len..]).unwrap();
+        assert_eq!(response.values, vec![Value::Str(long)]);
+    }
+
+    #[test]
+    fn int_v10_round_trip() {
+        for (val, len) in &[
+            (-5, 1),
+            (-1, 1),
+            (127, 1),
+            (128, 2),
+            (-128, 1),
+            (-129, 2),
+            (i32::MIN as i64, 4),
+            (i32::MAX as i64, 4),
+        ] {
+            let value = Value::Int(*val);
+            let data = Serializer::new()
+                .with_version(Version::V10)
+                .to_exact_vec(&value)
+                .unwrap();
+            // header and tag
+            assert_eq!(data.len(), 5 + 1 + len, "{}", val);
+            assert_eq!(parse_response(&data), value);
+        }
+    }
+
+    #[test]
+    fn null_by_version() {
+        let value = Value::Array(vec![Value::Int(-5), Value::Int(300), Value::Null]);
+        let mut buffer = [0u8; 32];
+
+        let mut serializer = Serializer::new().with_version(Version::V10);
+        assert_eq!(
+            serializer.write_response(&mut buffer, &value),
+            Err("null is not supported by protocol 1.0")
+        );
+
+        for &version in &[Version::V20, Version::V21, Version::V30] {
+            let mut serializer = Serializer::new().with_version(version);
+            let cnt = serializer.write_response(&mut buffer, &value).unwrap();
+            assert_eq!(&buffer[2..4], &[version.major(), version.minor()]);
+            assert_eq!(parse_response(&buffer[..cnt]), value, "version {}", version);
+        }
+    }
+
+    #[test]
+    fn int_and_datetime_v10() {
+        let mut buffer = [0u8; 32];
+
+        let value = Value::Int(0x12345);
+        let mut serializer = Serializer::new().with_version(Version::V10);
+        let cnt = serializer.write_response(&mut buffer, &value).unwrap();
+        assert_eq!(&buffer[5..cnt], &[0x0b, 0x45, 0x23, 0x01]);
+        assert_eq!(parse_response(&buffer[..cnt]), value);
+
+        let dt = Value::DateTime(DateTimeVer30::from_unix(1485520759, -8));
+        let mut serializer = Serializer::new().with_version(Version::V10);
+        let cnt = serializer.write_response(&mut buffer, &dt).unwrap();
+        assert_eq!(cnt, 5 + 11);
+        let_extract!(
+            Value::DateTime(v),
+            parse_response(&buffer[..cnt]),
+            unreachable!()
+        );
+        assert_eq!(v.timestamp(), Some(1485520759));
+
+        let mut serializer = Serializer::new().with_version(Version::V10);
+        assert_eq!(
+            serializer.write_response(&mut buffer, &Value::Int(1 << 40)),
+            Err("integer out of range of protocol 1.0")
+        );
+    }
+
+    #[test]
+    fn string_v10() {
+        let mut buffer = [0u8; 512];
+
+        // length 300 needs 2 length bytes, octets field holds their count
+        let value = Value::Str("x".repeat(300));
+        let mut serializer = Serializer::new().with_version(Version::V10);
+        let cnt = serializer.write_response(&mut buffer, &value).unwrap();
+        assert_eq!(cnt, 5 + 3 + 300);
+        assert_eq!(&buffer[5..8], &[STRING_ID | 2, 0x2c, 0x01]);
+        assert_eq!(parse_response(&buffer[..cnt]), value);
+
+        let value = Value::Array(vec![Value::Binary(vec![1, 2])]);
+        let mut serializer = Serializer::new().with_version(Version::V10);
+        let cnt = serializer.write_response(&mut buffer, &value).unwrap();
+        assert_eq!(&buffer[5..cnt], &[ARRAY_ID | 1, 1, BIN_ID | 1, 2, 1, 2]);
+        assert_eq!(parse_response(&buffer[..cnt]), value);
+    }
+
+    #[test]
+    fn int_v2_tags() {
+        let mut buffer = [0u8; 32];
+
+        // protocol 2.x peers may reject INT_ID, even small values use
+        // Integer8 positive and negative tags
+        for &version in &[Version::V20, Version::V21] {
+            for &(v, id) in &[
+                (5, U_VINT_ID),
+                (0, U_VINT_ID),
+                (-5, VINT_ID),
+                (i64::MAX, U_VINT_ID),
+                (i64::MIN, VINT_ID),
+            ] {
+                let mut serializer = Serializer::new().with_version(version);
+                let cnt = serializer
+                    .write_response(&mut buffer, &Value::Int(v))
+                    .unwrap();
+                assert_eq!(buffer[5] & TYPE_MASK, id, "{} in {}", v, version);
+
+                let mut tree = ValueTreeBuilder::new();
+                let res = Tokenizer::new_frpc().parse(&buffer[..cnt], &mut tree);
+                assert_eq!(res, Ok((false, cnt)));
+                assert_eq!(tree.values, vec![Value::Int(v)]);
+            }
+
+            let mut serializer = Serializer::new().with_version(version);
+            serializer.write_fault(&mut buffer, 5, "x").unwrap();
+            assert_eq!(buffer[5], U_VINT_ID);
+        }
+    }
+
+    #[test]
+    fn fixed_int_width() {
+        let mut buffer = [0u8; 32];
+
+        for &(version, head) in &[
+            (Version::V30, INT_ID | 3),
+            (Version::V21, U_VINT_ID | 3),
+            (Version::V10, INT_ID | 4),
+        ] {
+            let mut serializer = Serializer::new().with_version(version).int_width(4);
+            let cnt = serializer
+                .write_response(&mut buffer, &Value::Int(5))
+                .unwrap();
+            assert_eq!(cnt, 5 + 5, "{}", version);
+            assert_eq!(buffer[5], head, "{}", version);
+            assert_eq!(parse_response(&buffer[..cnt]), Value::Int(5));
+        }
+
+        // wider values are not truncated
+        let value = Value::Int(1 << 40);
+        let mut serializer = Serializer::new().int_width(4);
+        let cnt = serializer.write_response(&mut buffer, &value).unwrap();
+        assert_eq!(parse_response(&buffer[..cnt]), value);
+    }
+
+    #[test]
+    fn datetime_fields_round_trip() {
+        let mut buffer = [0u8; 32];
+        // every field non-zero and using its highest bit
+        let dt = DateTimeVer30 {
+            time_zone: 4,
+            unix_time: -1,
+            week_day: 6,
+            sec: 45,
+            min: 37,
+            hour: 19,
+            day: 23,
+            month: 11,
+            year: 2041,
+        };
+
+        for &version in &[Version::V10, Version::V21, Version::V30] {
+            let value = Value::DateTime(dt);
+            let mut serializer = Serializer::new().with_version(version);
+            let cnt = serializer.write_response(&mut buffer, &value).unwrap();
+            let_extract!(
+                Value::DateTime(v),
+                parse_response(&buffer[..cnt]),
+                unreachable!()
+            );
+            assert_eq!(
+                (v.week_day, v.sec, v.min, v.hour),
+                (6, 45, 37, 19),
+                "{}",
+                version
+            );
+            assert_eq!((v.day, v.month, v.year), (23, 11, 2041), "{}", version);
+            assert_eq!(v.time_zone, 4);
+        }
+    }
+
+    #[test]
+    fn datetime_year_clamping() {
+        let mut buffer: [u8; 15] = [0; 15];
+        let dt = DateTimeVer30::from_unix(1_600_000_000, 0);
+
+        // high 8 bits of year offset are stored in last byte
+        for &(year, high) in &[(1500, 0x00), (2021, (421 >> 3) as u8), (4000, 0xff)] {
+            let dt = DateTimeVer30 { year, ..dt };
+            assert_eq!(write_datetime_v30(&dt, &mut buffer), Ok(15));
+            assert_eq!(buffer[14], high, "year {}", year);
+            assert_eq!(LittleEndian::read_i64(&buffer[2..]), 1_600_000_000);
+        }
+    }
+
+    #[test]
+    fn datetime_negative_zone() {
+        let mut buffer: [u8; 64] = [0; 64];
+        let mut serializer = Serializer::new();
+
+        // 2017-01-27 12:39:19 UTC in -2:00
+        let dt = Value::DateTime(DateTimeVer30::from_unix(1485520759, -8));
+        let cnt = serializer.write_response(&mut buffer, &dt).unwrap();
+        assert_eq!(buffer[6], 0xf8); // zone byte follows response and type
+        let_extract!(
+            Value::DateTime(v),
+            parse_response(&buffer[..cnt]),
+            unreachable!()
+        );
+        assert_eq!(v.time_zone, -8);
+        assert_eq!(v.offset_seconds(), -2 * 3600);
+        assert_eq!(v.timestamp(), Some(1485520759));
+
+        // unrepresentable time, 2024-08-08 00:30:00 in -2:00
+        let dt = Value::DateTime(DateTimeVer30 {
+            time_zone: -8,
+            unix_time: -1,
+            week_day: 4,
+            sec: 0,
+            min: 30,
+            hour: 0,
+            day: 8,
+            month: 8,
+            year: 2024,
+        });
+        serializer.reset();
+        let cnt = serializer.write_response(&mut buffer, &dt).unwrap();
+        let_extract!(
+            Value::DateTime(v),
+            parse_response(&buffer[..cnt]),
+            unreachable!()
+        );
+        assert_eq!(v.timestamp(), Some(1723084200));
+        assert_eq!(Value::DateTime(v).to_string(), "2024-08-08 02:30:00");
+    }
+
+    #[test]
+    fn data_head_small_buffer() {
+        let mut buffer = [0xffu8; 2];
+        assert_eq!(
+            write_data_head(1 << 20, &mut buffer),
+            Err("not enought space")
+        );
+        assert_eq!(
+            write_data_head(1, &mut buffer[..0]),
+            Err("not enought space")
+        );
+
+        assert_eq!(write_data_head(0, &mut buffer), Ok(1));
+        assert_eq!(buffer[0], FRPS_DATA_ID);
+
+        let mut buffer = [0u8; 3];
+        assert_eq!(write_data_head(0x1234, &mut buffer), Ok(3));
+        assert_eq!(buffer, [0x01, 0x34, 0x12]);
+
+        // 3 bytes of size need 4 bytes long field
+        let mut buffer = [0u8; 5];
+        assert_eq!(write_data_head(0x12_3456, &mut buffer), Ok(5));
+        assert_eq!(buffer, [0x02, 0x56, 0x34, 0x12, 0x00]);
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn data_head_8_octets() {
+        // size needing 5 bytes is written with mask 4 and 8 length bytes
+        let size = (1usize << 32) + 5;
+        let mut head = [0u8; 9];
+        assert_eq!(write_data_head(size, &mut head), Ok(9));
+        assert_eq!(head, [0x04, 0x05, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]);
+
+        // response "x" followed by beginning of the data
+        let mut data = hex::decode("ca11020170200178").unwrap();
+        data.extend_from_slice(&head);
+        data.extend_from_slice(b"abc");
+
+        let mut tokenizer = Tokenizer::new_frps();
+        let mut tree = ValueTreeBuilder::new();
+        assert_eq!(tokenizer.parse(&data, &mut tree), Ok((true, data.len())));
+        assert_eq!(tree.data_len(), size);
+        assert_eq!(tree.data, b"abc");
+
+        // mask 3 has no length field assigned
+        data[8] = 0x03;
+        let mut tokenizer = Tokenizer::new_frps();
+        let mut tree = ValueTreeBuilder::new();
+        assert_eq!(tokenizer.parse(&data, &mut tree), Err(9));
+    }
+
+    #[test]
+    fn exact_vec() {
+        let mut members = HashMap::new();
+        members.insert("name".to_owned(), Value::Str("x".repeat(300)));
+        members.insert(
+            "list".to_owned(),
+            Value::Array((0..20).map(Value::Int).collect()),
+        );
+        members.insert("blob".to_owned(), Value::Binary(vec![7; 70000]));
+        let values = vec![
+            Value::Int(-1),
+            Value::Int(i64::MAX),
+            Value::Struct(members),
+            Value::Array(vec![Value::Null, Value::Bool(true), Value::Double(0.5)]),
+            Value::datetime_from_unix(1_500_000_000),
+        ];
+
+        for version in &[Version::V21, Version::V30] {
+            let mut serializer = Serializer::new().with_version(*version).int_width(4);
+            for value in &values {
+                let data = serializer.to_exact_vec(value).unwrap();
+                assert_eq!(data.len(), 5 + serializer.serialized_size(value).unwrap());
+                let tree = crate::parse_frpc(&data).unwrap();
+                assert_eq!(&tree.values[0], value);
+            }
+        }
+
+        let dt = Value::datetime_from_unix(0);
+        assert_eq!(Serializer::new().serialized_size(&dt), Ok(15));
+        let serializer = Serializer::new().with_version(Version::V10);
+        assert_eq!(serializer.serialized_size(&dt), Ok(11));
+        assert_eq!(
+            serializer.serialized_size(&Value::Array(vec![Value::Null])),
+            Err("null is not supported by protocol 1.0")
+        );
+    }
+
+    #[test]
+    fn binary_stream() {
+        let blob: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+
+        // response header followed by streamed value
+        for &version in &[Version::V30, Version::V10] {
+            let serializer = Serializer::new().with_version(version);
+            let mut magic = [0u8; 5];
+            let cnt = write_magic(version, RESPOSE_ID, &mut magic).unwrap();
+            let mut data = magic[..cnt].to_vec();
+            let cnt = serializer.write_binary_stream(&mut data, blob.len(), io::Cursor::new(&blob));
+            assert_eq!(cnt.unwrap(), 4 + blob.len());
+
+            let_extract!(Value::Binary(v), parse_response(&data), unreachable!());
+            assert_eq!(v.len(), blob.len());
+            assert_eq!((v[0], v[v.len() - 1]), (blob[0], blob[blob.len() - 1]));
+        }
+
+        let serializer = Serializer::new();
+        let err = serializer.write_binary_stream(vec![], 10, io::Cursor::new(&blob[..5]));
+        assert_eq!(err.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
 }
@@ -0,0 +1,140 @@
+use crate::datetime::DateTimeVer30;
+use crate::tokenizer::Callback;
+
+/// Callback forwarding every token to both `first` and `second`, e.g. to
+/// build value tree and log tokens at once. Tokenizing stops when any of
+/// them returns false.
+#[derive(Debug)]
+pub struct Tee<A: Callback, B: Callback> {
+    pub first: A,
+    pub second: B,
+}
+
+impl<A: Callback, B: Callback> Tee<A, B> {
+    pub fn new(first: A, second: B) -> Tee<A, B> {
+        Tee { first, second }
+    }
+
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+}
+
+impl<A: Callback, B: Callback> Callback for Tee<A, B> {
+    fn error(&mut self, msg: &str) {
+        self.first.error(msg);
+        self.second.error(msg);
+    }
+
+    // both callbacks are always called, `&` doesn't short-circuit
+    fn version(&mut self, major_version: u8, minor_version: u8) -> bool {
+        self.first.version(major_version, minor_version)
+            & self.second.version(major_version, minor_version)
+    }
+
+    fn call(&mut self, method: &str, length: usize) -> bool {
+        self.first.call(method, length) & self.second.call(method, length)
+    }
+
+    fn response(&mut self) -> bool {
+        self.first.response() & self.second.response()
+    }
+
+    fn fault(&mut self) -> bool {
+        self.first.fault() & self.second.fault()
+    }
+
+    fn stream_data(&mut self, v: &[u8]) -> bool {
+        self.first.stream_data(v) & self.second.stream_data(v)
+    }
+
+    fn stream_data_begin(&mut self, len: usize) -> bool {
+        self.first.stream_data_begin(len) & self.second.stream_data_begin(len)
+    }
+
+    fn null(&mut self) -> bool {
+        self.first.null() & self.second.null()
+    }
+
+    fn integer(&mut self, v: i64) -> bool {
+        self.first.integer(v) & self.second.integer(v)
+    }
+
+    fn boolean(&mut self, v: bool) -> bool {
+        self.first.boolean(v) & self.second.boolean(v)
+    }
+
+    fn double_number(&mut self, v: f64) -> bool {
+        self.first.double_number(v) & self.second.double_number(v)
+    }
+
+    fn datetime(&mut self, v: DateTimeVer30) -> bool {
+        self.first.datetime(v) & self.second.datetime(v)
+    }
+
+    fn string_begin(&mut self, len: usize) -> bool {
+        self.first.string_begin(len) & self.second.string_begin(len)
+    }
+
+    fn string_data(&mut self, v: &[u8], len: usize) -> bool {
+        self.first.string_data(v, len) & self.second.string_data(v, len)
+    }
+
+    fn binary_begin(&mut self, len: usize) -> bool {
+        self.first.binary_begin(len) & self.second.binary_begin(len)
+    }
+
+    fn binary_data(&mut self, v: &[u8], len: usize) -> bool {
+        self.first.binary_data(v, len) & self.second.binary_data(v, len)
+    }
+
+    fn array_begin(&mut self, len: usize) -> bool {
+        self.first.array_begin(len) & self.second.array_begin(len)
+    }
+
+    fn struct_begin(&mut self, len: usize) -> bool {
+        self.first.struct_begin(len) & self.second.struct_begin(len)
+    }
+
+    fn struct_key(&mut self, v: &[u8], len: usize) -> bool {
+        self.first.struct_key(v, len) & self.second.struct_key(v, len)
+    }
+
+    fn value_end(&mut self) -> bool {
+        self.first.value_end() & self.second.value_end()
+    }
+
+    /// Value is skipped only when both callbacks agree on its size
+    fn unknown_type(&mut self, tag: u8) -> Option<usize> {
+        match (self.first.unknown_type(tag), self.second.unknown_type(tag)) {
+            (Some(a), Some(b)) if a == b => Some(a),
+            _ => None,
+        }
+    }
+
+    fn message_end(&mut self) -> bool {
+        self.first.message_end() & self.second.message_end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::tests::Probe;
+    use crate::{Tokenizer, Value, ValueTreeBuilder};
+
+    #[test]
+    fn tree_and_counter() {
+        // m(1, ("x"))
+        let data = hex::decode("ca11030068016d08025801200178").unwrap();
+        let mut tee = Tee::new(ValueTreeBuilder::new(), Probe::new(0));
+        let mut tokenizer = Tokenizer::new_frpc();
+        assert_eq!(tokenizer.parse(&data, &mut tee), Ok((false, data.len())));
+
+        let (tree, counter) = tee.into_inner();
+        assert_eq!(tree.to_string(), "m(1, (\"x\"))");
+        assert_eq!(tree.values[0], Value::Int(1));
+        // version, call, int, array begin, string begin, data, 2x value end
+        assert_eq!(counter.tokens, 8);
+    }
+}
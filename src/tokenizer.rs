@@ -1,6 +1,10 @@
 use crate::common::*;
+use crate::datetime::DateTimeVer30;
+use crate::encoding::zigzag_decode;
+use crate::version::Version;
 use byteorder::{ByteOrder, LittleEndian};
 use std::cmp;
+use std::convert::TryFrom;
 use std::fmt::Debug;
 use std::str;
 
@@ -11,6 +15,7 @@ enum States {
     CallNameSize,
     Response,
     Fault,
+    FaultDetail,
     CallName { length: usize, processed: usize },
     Value,
     ValueInt,
@@ -18,6 +23,7 @@ enum States {
     Pop,
     IntegerHead { head: u8 },
     Integer1 { is_negative: bool, bytes_cnt: usize },
+    IntegerSigned { bytes_cnt: usize },
     Integer3 { bytes_cnt: usize },
     Double,
     ArrayInit { octects: usize },
@@ -32,10 +38,12 @@ enum States {
     StructKeyHead,
     StructKey { length: usize, processed: usize },
     DateTime,
+    DateTimeVer30,
     Finish,
     DataInit,
     DataLen { octects: usize },
     Data { length: usize, processed: usize },
+    Skip { remaining: usize },
 }
 
 /// Tokenizer calls methods in this trait when Token is found in input data
@@ -43,7 +51,9 @@ pub trait Callback {
     /** Parsing always stop after this callback return. */
     fn error(&mut self, msg: &str);
 
-    /* Stop on false, continue on true */
+    /// Called with version of message, return false to reject version the
+    /// callback doesn't support. Tokenizing stops with "unsupported
+    /// version" error, malformed version is "bad protocol version".
     fn version(&mut self, major_version: u8, minor_version: u8) -> bool;
 
     /* Stop on false, continue on true */
@@ -58,6 +68,12 @@ pub trait Callback {
     /* Stop on false, continue on true */
     fn stream_data(&mut self, v: &[u8]) -> bool;
 
+    /// Called when FRPS data block of `len` bytes begins, its content is
+    /// passed to `stream_data`. Stop on false, continue on true
+    fn stream_data_begin(&mut self, _len: usize) -> bool {
+        true
+    }
+
     /* Stop on false, continue on true */
     fn null(&mut self) -> bool;
 
@@ -65,7 +81,7 @@ pub trait Callback {
     fn integer(&mut self, v: i64) -> bool;
     fn boolean(&mut self, v: bool) -> bool;
     fn double_number(&mut self, v: f64) -> bool;
-    fn datetime(&mut self, v: i64) -> bool;
+    fn datetime(&mut self, v: DateTimeVer30) -> bool;
     /** Called when we reached begin of string with len */
     fn string_begin(&mut self, len: usize) -> bool;
     /* send data chunk 'v' with size smaller or equal of total length in 'len'*/
@@ -82,6 +98,21 @@ pub trait Callback {
 
     /** Called when reached end of string, binary, array or struct */
     fn value_end(&mut self) -> bool;
+
+    /// Called for type byte `tag` unknown to this implementation. Framing of
+    /// such value can't be known by tokenizer, return count of bytes
+    /// following `tag` to skip the value or `None` to stop with error.
+    fn unknown_type(&mut self, _tag: u8) -> Option<usize> {
+        None
+    }
+
+    /// Called once when response or fault is complete. Call arguments,
+    /// FRPS data and FRPS fault detail have no terminator so only caller
+    /// knows where they end.
+    /// Stop on false, continue on true
+    fn message_end(&mut self) -> bool {
+        true
+    }
 }
 
 #[derive(Debug)]
@@ -102,7 +133,7 @@ impl<'a> SourcePtr<'a> {
 
     /// return slice for unconsumed part of data with `cnt` length
     fn data(&self, cnt: usize) -> &[u8] {
-        assert!(self.pos + cnt <= self.src.len());
+        debug_assert!(self.pos + cnt <= self.src.len());
         &self.src[self.pos..self.pos + cnt]
     }
 
@@ -111,6 +142,14 @@ impl<'a> SourcePtr<'a> {
         self.pos += cnt;
     }
 
+    /// return slice with `cnt` bytes of unconsumed data and consume them
+    fn take(&mut self, cnt: usize) -> &'a [u8] {
+        debug_assert!(self.pos + cnt <= self.src.len());
+        let d = &self.src[self.pos..self.pos + cnt];
+        self.pos += cnt;
+        d
+    }
+
     /// return number of bytes consumed.
     fn consumed(&self) -> usize {
         //assert_eq!(self.pos, self.src.len());
@@ -144,8 +183,8 @@ impl Buffer {
     // Try to read `need` bytes from `src` and update src
     // return true when enough data was read, false otherwise
     fn consume(&mut self, need: usize, src: &mut SourcePtr) -> bool {
-        assert!(need >= self.cnt);
-        assert!(need <= self.data.len());
+        debug_assert!(need >= self.cnt);
+        debug_assert!(need <= self.data.len());
 
         let cnt = cmp::min(need - self.cnt, src.available());
         if cnt > 0 {
@@ -159,8 +198,24 @@ impl Buffer {
         // do we have all data ?
         need == self.cnt
     }
+
+    // Return `need` bytes of fixed size field when all are available. When
+    // whole field is in `src` it is returned directly without copying into
+    // buffer, otherwise data are accumulated in buffer across calls.
+    fn read<'s: 'b, 'b>(&'b mut self, need: usize, src: &mut SourcePtr<'s>) -> Option<&'b [u8]> {
+        if self.cnt == 0 && src.available() >= need {
+            return Some(src.take(need));
+        }
+
+        if self.consume(need, src) {
+            Some(&self.data[..need])
+        } else {
+            None
+        }
+    }
 }
 
+#[derive(Debug)]
 enum Context {
     Init,
     Response,
@@ -169,6 +224,23 @@ enum Context {
     Data,
 }
 
+/// Framing state of tokenized message, see `Tokenizer::state`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenizerState {
+    /// Magic, version, message type or method name is not complete
+    Header,
+    /// Value is not complete, more data are required
+    InValue,
+    /// Message is complete, no more data are accepted
+    Complete,
+    /// Message may be complete, optional call arguments or FRPS data may
+    /// follow
+    ExpectingOptionalArgs,
+    /// Parsing failed, tokenizer has to be reset
+    Error,
+}
+
+#[derive(Debug)]
 pub struct Tokenizer {
     // Here we store state for recursive values as array and structs
     stack: Vec<States>,
@@ -179,6 +251,31 @@ pub struct Tokenizer {
     /// When `true` tokenizer is ready to accept methods arguments (which are optional)
     context: Context,
     is_frps: bool,
+    /// When `true` input is single FRPS data block without message header
+    data_only: bool,
+    /// When `true` zero bytes after complete message are skipped
+    tolerate_trailing_zeros: bool,
+    /// When `true` reserved bits of type info have to be zero
+    strict: bool,
+    /// When `true` `Callback::message_end` was already called
+    message_ended: bool,
+    /// When `true` parsing failed and tokenizer has to be reset
+    failed: bool,
+    /// Total length of input when known in advance
+    input_length: Option<usize>,
+    /// Bytes processed by previous `parse` calls
+    offset: usize,
+    /// Maximal count of call arguments
+    max_call_args: usize,
+    /// When `true` datetime in 3.0 layout is accepted in older message
+    lenient_datetime: bool,
+    /// Messages of lower protocol version are rejected
+    min_version: Version,
+    /// Maximal length of string, binary and stream data chunk passed to
+    /// callback
+    max_chunk_size: usize,
+    /// Count of transport header bytes skipped before message
+    skip_prefix: usize,
 }
 
 impl Tokenizer {
@@ -191,6 +288,18 @@ impl Tokenizer {
             version_minor: 0,
             context: Context::Init,
             is_frps: false,
+            data_only: false,
+            tolerate_trailing_zeros: false,
+            strict: false,
+            message_ended: false,
+            failed: false,
+            input_length: None,
+            offset: 0,
+            max_call_args: usize::MAX,
+            lenient_datetime: false,
+            min_version: Version::V10,
+            max_chunk_size: usize::MAX,
+            skip_prefix: 0,
         }
     }
 
@@ -203,9 +312,68 @@ impl Tokenizer {
             version_minor: 0,
             context: Context::Init,
             is_frps: true,
+            data_only: false,
+            tolerate_trailing_zeros: false,
+            strict: false,
+            message_ended: false,
+            failed: false,
+            input_length: None,
+            offset: 0,
+            max_call_args: usize::MAX,
+            lenient_datetime: false,
+            min_version: Version::V10,
+            max_chunk_size: usize::MAX,
+            skip_prefix: 0,
         }
     }
 
+    /// Tokenizer for single FRPS data block (header and payload) without
+    /// message header. Stream data are passed to `Callback::stream_data`,
+    /// any data after the block is an error.
+    pub fn new_frps_data() -> Tokenizer {
+        Tokenizer {
+            stack: vec![States::Finish, States::DataInit],
+            buffer: Buffer::new(),
+
+            version_major: 0,
+            version_minor: 0,
+            context: Context::Data,
+            is_frps: true,
+            data_only: true,
+            tolerate_trailing_zeros: false,
+            strict: false,
+            message_ended: false,
+            failed: false,
+            input_length: None,
+            offset: 0,
+            max_call_args: usize::MAX,
+            lenient_datetime: false,
+            min_version: Version::V10,
+            max_chunk_size: usize::MAX,
+            skip_prefix: 0,
+        }
+    }
+
+    /// Silently consume `0x00` bytes following complete message instead of
+    /// reporting "data after end". Useful for transports padding messages
+    /// to block boundary. Non-zero trailing data is still an error.
+    ///
+    /// In FRPS zero byte following call or response is empty data block, so
+    /// such padding is accepted regardless of this option, which affects
+    /// only padding after fault.
+    pub fn tolerate_trailing_zeros(mut self, tolerate: bool) -> Tokenizer {
+        self.tolerate_trailing_zeros = tolerate;
+        self
+    }
+
+    /// Reject double, datetime, call, response and fault whose additional
+    /// type info is not zero as specification requires. Off by default
+    /// since some implementations don't clear these bits.
+    pub fn strict(mut self, strict: bool) -> Tokenizer {
+        self.strict = strict;
+        self
+    }
+
     /// return `true` when tokenizer expect data to tokenize to reach
     /// valid state
     fn need_data(&self) -> bool {
@@ -216,25 +384,171 @@ impl Tokenizer {
         }
     }
 
+    /// Datetime has 10 bytes in protocol 1.0 and 2.x but 14 bytes in 3.0.
+    /// Some servers send 3.0 datetime in older message, which is detected by
+    /// invalid month of datetime read in declared layout. By default such
+    /// datetime is an error, when `lenient` is `true` rest of 3.0 datetime is
+    /// read instead. Note that older datetime with month outside of 1-12 was
+    /// accepted as is before this option existed.
+    pub fn lenient_datetime(mut self, lenient: bool) -> Tokenizer {
+        self.lenient_datetime = lenient;
+        self
+    }
+
+    /// Limit count of call arguments, unlimited by default. Container
+    /// lengths are limited by `MAX_*` constants but call arguments have no
+    /// declared count.
+    pub fn max_call_args(mut self, max: usize) -> Tokenizer {
+        self.max_call_args = max;
+        self
+    }
+
+    /// Reject messages declaring protocol version lower than `version`,
+    /// e.g. obsolete 1.0 without null. All versions are accepted by default.
+    pub fn min_version(mut self, version: Version) -> Tokenizer {
+        self.min_version = version;
+        self
+    }
+
+    /// Limit length of chunks passed to `Callback::string_data`,
+    /// `binary_data` and `stream_data`, longer input is passed in more
+    /// calls. Unlimited by default, 0 is treated as 1.
+    pub fn max_chunk_size(mut self, size: usize) -> Tokenizer {
+        self.max_chunk_size = cmp::max(size, 1);
+        self
+    }
+
+    /// Discard first `n` bytes of input (e.g. fixed size transport header)
+    /// before message magic. Kept by `reset`, last call wins.
+    pub fn skip_prefix(mut self, n: usize) -> Tokenizer {
+        self.skip_prefix = n;
+        // replace prefix set by previous call
+        if let Some(States::Skip { .. }) = self.stack.last() {
+            self.stack.pop();
+        }
+        if n > 0 {
+            self.stack.push(States::Skip { remaining: n });
+        }
+        self
+    }
+
+    /// Set total length of input when it is known in advance (e.g. whole
+    /// message is in memory). Arrays, structs, strings and binaries
+    /// declaring more items than can fit into rest of input are rejected
+    /// before any memory is reserved for them. Kept by `reset`.
+    pub fn input_length(mut self, length: usize) -> Tokenizer {
+        self.input_length = Some(length);
+        self
+    }
+
+    /// Return framing state, so caller can decide whether to read more data
+    /// or treat message as done. FRPS fault may be followed by optional
+    /// detail struct, so it is `ExpectingOptionalArgs` after its message and
+    /// `Complete` only after detail or next byte.
+    pub fn state(&self) -> TokenizerState {
+        if self.failed {
+            return TokenizerState::Error;
+        }
+
+        match self.stack.last() {
+            None | Some(States::Finish) => TokenizerState::Complete,
+            Some(States::Init)
+            | Some(States::MessageType)
+            | Some(States::CallNameSize)
+            | Some(States::CallName { .. }) => TokenizerState::Header,
+            // transport prefix
+            Some(States::Skip { .. }) if matches!(self.stack[..], [States::Init, _]) => {
+                TokenizerState::Header
+            }
+            Some(States::FaultDetail) => TokenizerState::ExpectingOptionalArgs,
+            // call arguments and data following response value
+            Some(States::Value) | Some(States::DataInit)
+                if self.stack.len() == 1 && self.buffer.cnt == 0 =>
+            {
+                match self.context {
+                    Context::Call { .. } | Context::Data => TokenizerState::ExpectingOptionalArgs,
+                    // FRPC response ends by its value, so only value after
+                    // header may be waiting here
+                    Context::Response if self.is_frps => TokenizerState::ExpectingOptionalArgs,
+                    _ => TokenizerState::InValue,
+                }
+            }
+            _ => TokenizerState::InValue,
+        }
+    }
+
+    /// Innermost array or struct whose items are not all tokenized yet
+    pub(crate) fn open_container(&self) -> Option<&'static str> {
+        self.stack.iter().rev().find_map(|state| match state {
+            States::ArrayItems { .. } => Some("array"),
+            States::StructItem { .. } => Some("struct"),
+            _ => None,
+        })
+    }
+
+    /// Protocol version of message, `None` until header is tokenized
+    pub fn version(&self) -> Option<Version> {
+        Version::new(self.version_major, self.version_minor)
+    }
+
+    /// Return current state, useful when looking why parsing doesn't
+    /// continue
+    pub fn debug_state(&self) -> String {
+        match self.stack.last() {
+            Some(state) => format!("{:?}", state),
+            None => "Empty".to_owned(),
+        }
+    }
+
     /// Prepare tokenizer for reuse with new stream
     pub fn reset(&mut self) {
         self.stack.clear();
         self.buffer.reset();
         self.version_major = 0;
         self.version_minor = 0;
-        self.context = Context::Init;
-        self.stack.push(States::Init);
+        self.message_ended = false;
+        self.failed = false;
+        self.offset = 0;
+        if self.data_only {
+            self.context = Context::Data;
+            self.stack.push(States::Finish);
+            self.stack.push(States::DataInit);
+        } else {
+            self.context = Context::Init;
+            self.stack.push(States::Init);
+        }
+        if self.skip_prefix > 0 {
+            self.stack.push(States::Skip {
+                remaining: self.skip_prefix,
+            });
+        }
     }
 
     /// Function tokenize `src` and call `cb` for storing Tokens.
     ///
     /// Return Ok (`true` if more data are expected and how many `bytes`
     /// was processed) or error description
-    #[allow(clippy::cognitive_complexity)]
     pub fn parse<T: Callback + Debug>(
         &mut self,
         src: &[u8],
         cb: &mut T,
+    ) -> Result<(bool, usize), usize> {
+        let res = self.tokenize(src, cb);
+        match res {
+            Ok((_, processed)) => self.offset += processed,
+            Err(_) => self.failed = true,
+        }
+        res
+    }
+
+    // Malformed input must be reported by `cb.error` and `Err`, never by
+    // panic. Assertions (debug only) guard internal invariants which input
+    // data can't break.
+    #[allow(clippy::cognitive_complexity)]
+    fn tokenize<T: Callback + Debug>(
+        &mut self,
+        src: &[u8],
+        cb: &mut T,
     ) -> Result<(bool, usize), usize> {
         let mut src = SourcePtr::new(src);
 
@@ -244,7 +558,7 @@ impl Tokenizer {
                 States::Init => {
                     // first 4 bytes is header with magic and version
                     if !self.buffer.consume(4, &mut src) {
-                        assert!(src.is_all_consumed());
+                        debug_assert!(src.is_all_consumed());
                         return Ok((true, src.consumed()));
                     }
 
@@ -270,9 +584,14 @@ impl Tokenizer {
                         return Err(src.pos);
                     }
 
-                    if !cb.version(self.version_major, self.version_minor) {
-                        // dbg!(src.pos, &src.src[src.pos..], cb);
-                        cb.error("cb::version invalid version");
+                    let version = Version::new(self.version_major, self.version_minor);
+                    if version < Some(self.min_version)
+                        || !cb.version(self.version_major, self.version_minor)
+                    {
+                        cb.error(&format!(
+                            "unsupported version {}.{}",
+                            self.version_major, self.version_minor
+                        ));
                         return Err(src.pos);
                     }
 
@@ -283,10 +602,15 @@ impl Tokenizer {
                 States::MessageType => {
                     // first byte is message type
                     if !self.buffer.consume(1, &mut src) {
-                        assert!(src.is_all_consumed());
+                        debug_assert!(src.is_all_consumed());
                         return Ok((true, src.consumed()));
                     }
 
+                    if self.strict && (self.buffer.data[0] & OCTET_CNT_MASK) != 0 {
+                        cb.error("non-zero reserved bits");
+                        return Err(src.pos);
+                    }
+
                     match self.buffer.data[0] & TYPE_MASK {
                         CALL_ID => {
                             *state = States::CallNameSize;
@@ -311,7 +635,7 @@ impl Tokenizer {
                 States::CallNameSize => {
                     // first byte is method name length
                     if !self.buffer.consume(1, &mut src) {
-                        assert!(src.is_all_consumed());
+                        debug_assert!(src.is_all_consumed());
                         return Ok((true, src.consumed()));
                     }
 
@@ -330,20 +654,69 @@ impl Tokenizer {
                 }
 
                 States::CallName { length, processed } => {
+                    // `processed` counts bytes passed to callback, utf8
+                    // character split between chunks waits in buffer
+                    if self.buffer.cnt > 0 {
+                        let cnt = self.buffer.cnt + 1;
+                        if !self.buffer.consume(cnt, &mut src) {
+                            return Ok((true, src.consumed()));
+                        }
+                        match str::from_utf8(&self.buffer.data[..cnt]) {
+                            Ok(v) => {
+                                if !cb.call(v, *length) {
+                                    cb.error("cb::call in CallName failed");
+                                    return Err(src.pos);
+                                }
+                                *processed += cnt;
+                                self.buffer.reset();
+                            }
+                            // character is still incomplete
+                            Err(e) if e.error_len().is_none() && *processed + cnt < *length => {}
+                            Err(_) => {
+                                cb.error("invalid utf8 in method name");
+                                return Err(src.pos);
+                            }
+                        }
+                        if *processed == *length {
+                            *state = States::Pop;
+                        }
+                        continue;
+                    }
+
                     // read method name
                     let avail = cmp::min(*length - *processed, src.available());
                     if avail == 0 {
-                        assert!(src.is_all_consumed());
+                        debug_assert!(src.is_all_consumed());
                         return Ok((true, src.consumed()));
                     }
 
-                    let run = cb.call(str::from_utf8(src.data(avail)).unwrap(), *length);
+                    let data = &src.src[src.pos..src.pos + avail];
+                    let valid = match str::from_utf8(data) {
+                        Ok(v) => v,
+                        Err(e) if e.error_len().is_none() && *processed + avail < *length => {
+                            str::from_utf8(&data[..e.valid_up_to()]).unwrap()
+                        }
+                        Err(e) => {
+                            cb.error("invalid utf8 in method name");
+                            return Err(src.pos + e.valid_up_to());
+                        }
+                    };
+
+                    if !valid.is_empty() && !cb.call(valid, *length) {
+                        cb.error("cb::call in CallName failed");
+                        return Err(src.pos);
+                    }
 
-                    *processed += avail;
-                    src.advance(avail);
+                    *processed += valid.len();
+                    src.advance(valid.len());
+                    // keep beginning of split character
+                    let rest = avail - valid.len();
+                    if rest > 0 {
+                        self.buffer.consume(rest, &mut src);
+                    }
 
-                    if !run || *length != *processed {
-                        assert!(src.is_all_consumed());
+                    if *length != *processed {
+                        debug_assert!(src.is_all_consumed());
                         return Ok((true, src.consumed()));
                     }
 
@@ -377,16 +750,35 @@ impl Tokenizer {
                         return Err(src.pos);
                     }
 
-                    *state = States::Finish;
+                    // In FRPS fault can interrupt any value, whatever was
+                    // opened before is abandoned
+                    self.stack.clear();
+                    self.stack.push(States::Finish);
                     self.context = Context::Fault { args: 0 };
+                    if self.is_frps {
+                        self.stack.push(States::FaultDetail); // optional detail
+                    }
                     self.stack.push(States::ValueString); // message string
                     self.stack.push(States::ValueInt); // status code
                 }
 
+                States::FaultDetail => {
+                    // FRPS extension, fault may be followed by detail struct
+                    if src.is_all_consumed() {
+                        return Ok((false, src.consumed()));
+                    }
+                    if src.data(1)[0] & TYPE_MASK == STRUCT_ID {
+                        *state = States::Value;
+                    } else {
+                        // anything else is data after end of message
+                        self.stack.pop();
+                    }
+                }
+
                 States::Value => {
                     // first byte is value type
                     if !self.buffer.consume(1, &mut src) {
-                        assert!(src.is_all_consumed());
+                        debug_assert!(src.is_all_consumed());
                         // when we processing method arguments we dont need data
                         return Ok((self.need_data(), src.consumed()));
                     }
@@ -443,8 +835,9 @@ impl Tokenizer {
                                 0 => false,
                                 1 => true,
                                 _ => {
-                                    cb.error("invalid bool value");
-                                    return Err(src.pos);
+                                    // point at the tag byte
+                                    cb.error("invalid boolean: additional info must be 0 or 1");
+                                    return Err(src.pos - 1);
                                 }
                             };
                             let run = cb.boolean(v);
@@ -455,6 +848,12 @@ impl Tokenizer {
                             }
                             *state = States::Pop;
                         }
+                        DOUBLE_ID | DATETIME_ID | FAULT_RESPOSE_ID
+                            if self.strict && (self.buffer.data[0] & OCTET_CNT_MASK) != 0 =>
+                        {
+                            cb.error("non-zero reserved bits");
+                            return Err(src.pos);
+                        }
                         DOUBLE_ID => {
                             *state = States::Double;
                         }
@@ -463,16 +862,26 @@ impl Tokenizer {
                         }
                         FRPS_DATA_ID => {
                             if !self.is_frps {
+                                // Padding after last call argument
+                                if self.tolerate_trailing_zeros
+                                    && self.buffer.data[0] == 0
+                                    && self.stack.len() == 1
+                                {
+                                    if let Context::Call { args: _ } = self.context {
+                                        self.stack.pop();
+                                        self.stack.push(States::Finish);
+                                        self.buffer.reset();
+                                        continue;
+                                    }
+                                }
+
                                 cb.error("unknown type");
                                 return Err(src.pos);
                             }
 
-                            let octects: usize = match self.buffer.data[0] & OCTET_CNT_MASK {
-                                0 => 0,
-                                1 => 2,
-                                2 => 4,
-                                4 => 8,
-                                _ => {
+                            let octects = match data_len_octets(self.buffer.data[0]) {
+                                Some(v) => v,
+                                None => {
                                     cb.error("invalid type");
                                     return Err(src.pos);
                                 }
@@ -494,17 +903,35 @@ impl Tokenizer {
                             *state = States::Fault;
                         }
                         _ => {
-                            // dbg!(src.pos, &src.src[src.pos..], &self.buffer, cb);
-                            cb.error("unknown type");
-                            return Err(src.pos);
+                            let tag = self.buffer.data[0];
+                            match cb.unknown_type(tag) {
+                                Some(remaining) => *state = States::Skip { remaining },
+                                None => {
+                                    // type byte is always last consumed byte,
+                                    // position is counted from message start
+                                    cb.error(&format!(
+                                        "unknown type 0x{:02x} at {}",
+                                        tag,
+                                        self.offset + src.pos - 1
+                                    ));
+                                    return Err(src.pos);
+                                }
+                            }
                         }
                     }
 
                     self.buffer.reset();
 
-                    // Check if we are processing arguments for call and
-                    if let Context::Call { args: arg } = &mut self.context { 
-                        *arg += 1 
+                    // Count top level arguments of call, they have no declared
+                    // length so their count has to be limited here
+                    if let Context::Call { args: arg } = &mut self.context {
+                        if self.stack.len() == 1 {
+                            *arg += 1;
+                            if *arg > self.max_call_args {
+                                cb.error("too many call arguments");
+                                return Err(src.pos);
+                            }
+                        }
                     }
                 }
 
@@ -513,7 +940,7 @@ impl Tokenizer {
                 States::ValueInt => {
                     // first byte is value type
                     if !self.buffer.consume(1, &mut src) {
-                        assert!(src.is_all_consumed());
+                        debug_assert!(src.is_all_consumed());
                         // when we processing method arguments we dont need data
                         return Ok((true, src.consumed()));
                     }
@@ -550,14 +977,16 @@ impl Tokenizer {
                                 octects += 1;
                             }
 
-                            if self.version_major == 3 {
-                                *state = States::Integer3 { bytes_cnt: octects };
-                            } else {
-                                // negative number
-                                *state = States::Integer1 {
-                                    is_negative: false,
-                                    bytes_cnt: octects,
-                                };
+                            match self.version_major {
+                                3 => *state = States::Integer3 { bytes_cnt: octects },
+                                1 => *state = States::IntegerSigned { bytes_cnt: octects },
+                                // legacy int in 2.x is positive
+                                _ => {
+                                    *state = States::Integer1 {
+                                        is_negative: false,
+                                        bytes_cnt: octects,
+                                    }
+                                }
                             }
                         }
                         _ => {
@@ -574,7 +1003,7 @@ impl Tokenizer {
                 States::ValueString => {
                     // first byte is value type
                     if !self.buffer.consume(1, &mut src) {
-                        assert!(src.is_all_consumed());
+                        debug_assert!(src.is_all_consumed());
                         // when we processing method arguments we dont need data
                         return Ok((true, src.consumed()));
                     }
@@ -587,12 +1016,16 @@ impl Tokenizer {
 
                 // Protocol version 3.0 zigzag encoded int
                 States::Integer3 { bytes_cnt } => {
-                    if !self.buffer.consume(*bytes_cnt, &mut src) {
-                        assert!(src.is_all_consumed());
-                        return Ok((true, src.consumed()));
-                    }
+                    let data = match self.buffer.read(*bytes_cnt, &mut src) {
+                        Some(data) => data,
+                        None => {
+                            debug_assert!(src.is_all_consumed());
+                            return Ok((true, src.consumed()));
+                        }
+                    };
 
-                    let run = cb.integer(zigzag_decode(&self.buffer.data[0..*bytes_cnt]));
+                    let v = read_i64(data) as u64;
+                    let run = cb.integer(zigzag_decode(v));
                     if !run {
                         //dbg!(src.pos, &src.src[src.pos..], cb);
                         cb.error("cb::integer in Integer3 failed");
@@ -601,6 +1034,34 @@ impl Tokenizer {
                     *state = States::Pop;
                 }
 
+                // Protocol version 1.0 two's complement int of `bytes_cnt`
+                // bytes
+                States::IntegerSigned { bytes_cnt } => {
+                    if *bytes_cnt == 0 {
+                        cb.error("bad size");
+                        return Err(src.pos);
+                    }
+
+                    let data = match self.buffer.read(*bytes_cnt, &mut src) {
+                        Some(data) => data,
+                        None => {
+                            debug_assert!(src.is_all_consumed());
+                            return Ok((true, src.consumed()));
+                        }
+                    };
+
+                    // sign is in highest bit of last byte
+                    let shift = 64 - 8 * data.len();
+                    let v = (read_i64(data) << shift) >> shift;
+                    let run = cb.integer(v);
+                    if !run {
+                        //dbg!(src.pos, &src.src[src.pos..], cb);
+                        cb.error("cb::integer in IntegerSigned failed");
+                        return Err(src.pos);
+                    }
+                    *state = States::Pop;
+                }
+
                 States::Integer1 {
                     is_negative,
                     bytes_cnt,
@@ -610,15 +1071,27 @@ impl Tokenizer {
                         return Err(src.pos);
                     }
 
-                    if !self.buffer.consume(*bytes_cnt, &mut src) {
-                        assert!(src.is_all_consumed());
-                        return Ok((true, src.consumed()));
-                    }
+                    let data = match self.buffer.read(*bytes_cnt, &mut src) {
+                        Some(data) => data,
+                        None => {
+                            debug_assert!(src.is_all_consumed());
+                            return Ok((true, src.consumed()));
+                        }
+                    };
 
-                    let mut v = read_i64(&self.buffer.data[0..*bytes_cnt]);
+                    // Any octet count is accepted, not only minimal one.
+                    // Magnitude is unsigned so it can be out of i64 range.
+                    let mut v = read_i64(data) as u64 as i128;
                     if *is_negative {
                         v *= -1;
                     }
+                    let v = match i64::try_from(v) {
+                        Ok(v) => v,
+                        Err(_) => {
+                            cb.error("integer out of range");
+                            return Err(src.pos);
+                        }
+                    };
 
                     let run = cb.integer(v);
                     if !run {
@@ -660,17 +1133,28 @@ impl Tokenizer {
 
                     // read array len
                     if !self.buffer.consume(bytes_cnt, &mut src) {
-                        assert!(src.is_all_consumed());
+                        debug_assert!(src.is_all_consumed());
                         return Ok((true, src.consumed()));
                     }
 
-                    let cnt = read_i64(&self.buffer.data[0..bytes_cnt]) as usize;
+                    let cnt = match read_len(&self.buffer.data[0..bytes_cnt]) {
+                        Some(v) => v,
+                        None => {
+                            cb.error("length out of range");
+                            return Err(src.pos);
+                        }
+                    };
 
                     if cnt > MAX_STR_LENGTH {
                         cb.error("too large string");
                         return Err(src.pos);
                     }
 
+                    if !fits_input(self.input_length, self.offset + src.pos, cnt, 1) {
+                        cb.error("string length exceeds input");
+                        return Err(src.pos);
+                    }
+
                     let run = cb.string_begin(cnt);
                     if !run {
                         // dbg!(src.pos, &src.src[src.pos..], cb);
@@ -689,14 +1173,15 @@ impl Tokenizer {
                     // if *length == 0 {
                     //     return Err("Invalid string length");
                     // }
-                    assert!(*processed <= *length, "invalid state");
+                    debug_assert!(*processed <= *length, "invalid state");
                     // Do we have any string data? and string is not empty
                     if (*processed != *length) && src.is_all_consumed() {
                         return Ok((true, src.consumed()));
                     }
 
                     // Process available or missing part
-                    let cnt = cmp::min(src.available(), *length - *processed);
+                    let cnt =
+                        cmp::min(src.available(), *length - *processed).min(self.max_chunk_size);
                     let run = cb.string_data(src.data(cnt), *length);
                     if !run {
                         //dbg!(src.pos, &src.src[src.pos..], cb);
@@ -708,10 +1193,10 @@ impl Tokenizer {
                     src.advance(cnt);
                     *processed += cnt;
 
-                    // did we process all string data?
+                    // did we process all string data? Rest of input is passed
+                    // in next chunk
                     if processed != length {
-                        assert!(src.is_all_consumed());
-                        return Ok((true, src.consumed()));
+                        continue;
                     }
 
                     // string is completed
@@ -744,17 +1229,28 @@ impl Tokenizer {
 
                     // read array len
                     if !self.buffer.consume(bytes_cnt, &mut src) {
-                        assert!(src.is_all_consumed());
+                        debug_assert!(src.is_all_consumed());
                         return Ok((true, src.consumed()));
                     }
 
-                    let cnt = read_i64(&self.buffer.data[0..bytes_cnt]) as usize;
+                    let cnt = match read_len(&self.buffer.data[0..bytes_cnt]) {
+                        Some(v) => v,
+                        None => {
+                            cb.error("length out of range");
+                            return Err(src.pos);
+                        }
+                    };
 
                     if cnt > MAX_BIN_LENGTH {
                         cb.error("too large binary data");
                         return Err(src.pos);
                     }
 
+                    if !fits_input(self.input_length, self.offset + src.pos, cnt, 1) {
+                        cb.error("binary length exceeds input");
+                        return Err(src.pos);
+                    }
+
                     let run = cb.binary_begin(cnt);
                     if !run {
                         //dbg!(src.pos, &src.src[src.pos..], cb);
@@ -770,14 +1266,15 @@ impl Tokenizer {
                 }
 
                 States::BinData { length, processed } => {
-                    assert!(*processed <= *length, "invalid state");
+                    debug_assert!(*processed <= *length, "invalid state");
                     // Do we have any binary data and is not 0 length?
                     if (*processed != *length) && src.is_all_consumed() {
                         return Ok((true, src.consumed()));
                     }
 
                     // Process available or missing part
-                    let cnt = cmp::min(src.available(), *length - *processed);
+                    let cnt =
+                        cmp::min(src.available(), *length - *processed).min(self.max_chunk_size);
                     let run = cb.binary_data(src.data(cnt), *length);
                     if !run {
                         //dbg!(src.pos, &src.src[src.pos..], cb);
@@ -789,10 +1286,10 @@ impl Tokenizer {
                     src.advance(cnt);
                     *processed += cnt;
 
-                    // did we process all binary data?
+                    // did we process all binary data? Rest of input is passed
+                    // in next chunk
                     if processed != length {
-                        assert!(src.is_all_consumed());
-                        return Ok((true, src.consumed()));
+                        continue;
                     }
 
                     // binary is completed
@@ -820,17 +1317,29 @@ impl Tokenizer {
 
                     // read array len
                     if !self.buffer.consume(bytes_cnt, &mut src) {
-                        assert!(src.is_all_consumed());
+                        debug_assert!(src.is_all_consumed());
                         return Ok((true, src.consumed()));
                     }
 
-                    let cnt = read_i64(&self.buffer.data[0..bytes_cnt]) as usize;
+                    let cnt = match read_len(&self.buffer.data[0..bytes_cnt]) {
+                        Some(v) => v,
+                        None => {
+                            cb.error("length out of range");
+                            return Err(src.pos);
+                        }
+                    };
 
                     if cnt > MAX_ARRAY_LENGTH {
                         cb.error("too large array");
                         return Err(src.pos);
                     }
 
+                    // every item has at least type byte
+                    if !fits_input(self.input_length, self.offset + src.pos, cnt, 1) {
+                        cb.error("array length exceeds input");
+                        return Err(src.pos);
+                    }
+
                     let run = cb.array_begin(cnt);
                     if !run {
                         // dbg!(src.pos, &src.src[src.pos..], cb);
@@ -871,11 +1380,23 @@ impl Tokenizer {
 
                     // read struct len
                     if !self.buffer.consume(bytes_cnt, &mut src) {
-                        assert!(src.is_all_consumed());
+                        debug_assert!(src.is_all_consumed());
                         return Ok((true, src.consumed()));
                     }
 
-                    let items = read_i64(&self.buffer.data[0..bytes_cnt]) as usize;
+                    let items = match read_len(&self.buffer.data[0..bytes_cnt]) {
+                        Some(v) => v,
+                        None => {
+                            cb.error("length out of range");
+                            return Err(src.pos);
+                        }
+                    };
+
+                    // every member has at least key length and type byte
+                    if !fits_input(self.input_length, self.offset + src.pos, items, 2) {
+                        cb.error("struct length exceeds input");
+                        return Err(src.pos);
+                    }
 
                     let run = cb.struct_begin(items);
                     if !run {
@@ -906,7 +1427,7 @@ impl Tokenizer {
 
                 States::StructKeyHead => {
                     if !self.buffer.consume(1, &mut src) {
-                        assert!(src.is_all_consumed());
+                        debug_assert!(src.is_all_consumed());
                         return Ok((true, src.consumed()));
                     }
 
@@ -926,7 +1447,9 @@ impl Tokenizer {
                 }
 
                 States::StructKey { length, processed } => {
-                    assert!(*processed < *length, "invalid state");
+                    // State is replaced by Pop as soon as whole key is read,
+                    // so we never enter it with complete key
+                    debug_assert!(*processed < *length, "invalid state");
                     // Do we have any binary data?
                     if src.is_all_consumed() {
                         return Ok((true, src.consumed()));
@@ -945,21 +1468,24 @@ impl Tokenizer {
                     src.advance(cnt);
                     *processed += cnt;
 
-                    // did we process all binary data?
+                    // did we process all binary data? Rest of input is passed
+                    // in next chunk
                     if processed != length {
-                        assert!(src.is_all_consumed());
-                        return Ok((true, src.consumed()));
+                        continue;
                     }
 
                     *state = States::Pop;
                 }
                 States::Double => {
-                    if !self.buffer.consume(8, &mut src) {
-                        assert!(src.is_all_consumed());
-                        return Ok((true, src.consumed()));
-                    }
+                    let data = match self.buffer.read(8, &mut src) {
+                        Some(data) => data,
+                        None => {
+                            debug_assert!(src.is_all_consumed());
+                            return Ok((true, src.consumed()));
+                        }
+                    };
 
-                    let v = LittleEndian::read_f64(&self.buffer.data[0..8]);
+                    let v = LittleEndian::read_f64(data);
                     let run = cb.double_number(v);
                     if !run {
                         //dbg!(src.pos, &src.src[src.pos..], cb);
@@ -972,98 +1498,42 @@ impl Tokenizer {
                 States::DateTime => {
                     let bytes: usize = if self.version_major == 3 { 14 } else { 10 };
 
-                    if !self.buffer.consume(bytes, &mut src) {
-                        assert!(src.is_all_consumed());
-                        return Ok((true, src.consumed()));
-                    }
+                    let data = match self.buffer.read(bytes, &mut src) {
+                        Some(data) => data,
+                        None => {
+                            debug_assert!(src.is_all_consumed());
+                            return Ok((true, src.consumed()));
+                        }
+                    };
 
                     let val = if self.version_major == 3 {
-                        // struct DateTimeFormat3_t {
-                        //     uint8_t timeZone : 8;
-                        //     int64_t unixTime : 64;
-                        //     uint8_t weekDay : 3;
-                        //     uint8_t sec : 6;
-                        //     uint8_t minute : 6;
-                        //     uint8_t hour : 5;
-                        //     uint8_t day : 5;
-                        //     uint8_t month : 4;
-                        //     uint16_t year : 11;
-                        // } __attribute__((packed));
-                        let time_zone = (self.buffer.data[0] as i16) * 15 * 60;
-                        let unix_time = LittleEndian::read_i64(&self.buffer.data[1..]);
-                        let _week_day: u8 = self.buffer.data[9] & 0x07;
-                        let sec: u8 = ((self.buffer.data[9] & 0xf8) >> 3)
-                            | ((self.buffer.data[11] & 0x01) << 5);
-                        let min: u8 = (self.buffer.data[10] & 0x7e) >> 1;
-                        let hour: u8 = ((self.buffer.data[10] & 0x80) >> 7)
-                            | ((self.buffer.data[12] & 0x0f) << 1);
-                        let day: u8 = ((self.buffer.data[11] & 0xf0) >> 4)
-                            | ((self.buffer.data[12] & 0x01) << 4);
-                        let month: u8 = (self.buffer.data[12] & 0x1e) >> 1;
-                        let year = ((((self.buffer.data[12] as u16) & 0xe0) >> 5)
-                            | ((self.buffer.data[13] as u16) << 3)) + 1600;
-
-                        if unix_time != -1 {
-                            unix_time
-                        } else {
-                            let r = time::Date::try_from_ymd(year as i32, month, day);
-                            if r.is_err() {
-                                return Err(src.pos);
-                            }
-                            let r = r.unwrap().try_with_hms(hour, min, sec);
-                            if r.is_err() {
-                                return Err(src.pos);
-                            }
-                            let r = r.unwrap();
-                            r.assume_utc().to_offset(time::UtcOffset::seconds(time_zone as i32));
-                            r.assume_utc().timestamp()
-                        }
+                        decode_datetime_v30(data)
                     } else {
-                        // Verion 2.1 or 1.0
-
-                        // struct DateTimeFormat1_t {
-                        //     uint8_t timeZone : 8;
-                        //     int32_t unixTime : 32;
-                        //     uint8_t weekDay : 3;
-                        //     uint8_t sec : 6;
-                        //     uint8_t minute : 6;
-                        //     uint8_t hour : 5;
-                        //     uint8_t day : 5;
-                        //     uint8_t month : 4;
-                        //     uint16_t year : 11;
-                        // } __attribute__((packed));
-
-                        let time_zone = (self.buffer.data[0] as i16) * 15 * 60;
-                        let unix_time = LittleEndian::read_i32(&self.buffer.data[1..]) as i64;
-                        let _week_day = self.buffer.data[5] & 0x07;
-                        let sec = ((self.buffer.data[5] & 0xf8) >> 3)
-                            | ((self.buffer.data[6] & 0x01) << 5);
-                        let min = (self.buffer.data[6] & 0x7e) >> 1;
-                        let hour = ((self.buffer.data[6] & 0x80) >> 7)
-                            | ((self.buffer.data[7] & 0x0f) << 1);
-                        let day = ((self.buffer.data[7] & 0xf0) >> 4)
-                            | ((self.buffer.data[9] & 0x01) << 4);
-                        let month = (self.buffer.data[8] & 0x1e) >> 1;
-                        let year = ((((self.buffer.data[8] as u16) & 0xe0) >> 5)
-                            | ((self.buffer.data[9] as u16) << 3)) + 1600;
-
-                        if unix_time != -1 {
-                            unix_time
-                        } else {
-                            let r = time::Date::try_from_ymd(year as i32, month, day);
-                            if r.is_err() {
-                                return Err(src.pos);
-                            }
-                            let r = r.unwrap().try_with_hms(hour, min, sec);
-                            if r.is_err() {
-                                return Err(src.pos);
-                            }
-                            let r = r.unwrap();
-                            r.assume_utc().to_offset(time::UtcOffset::seconds(time_zone as i32));
-                            r.assume_utc().timestamp()
-                        }
+                        decode_datetime_v10(data)
                     };
 
+                    // month is zero when 3.0 datetime is read in older layout
+                    if !(1..=12).contains(&val.month) {
+                        if self.lenient_datetime && self.version_major != 3 {
+                            // keep what was read, rest of datetime follows
+                            let mut head = [0u8; 10];
+                            head.copy_from_slice(data);
+                            self.buffer.data[..10].copy_from_slice(&head);
+                            self.buffer.cnt = head.len();
+                            *state = States::DateTimeVer30;
+                            continue;
+                        }
+                        cb.error("invalid datetime");
+                        return Err(src.pos);
+                    }
+
+                    // -1 means unrepresentable time, calendar fields are used
+                    // instead and they have to be valid
+                    if val.timestamp().is_none() {
+                        cb.error("invalid datetime");
+                        return Err(src.pos);
+                    }
+
                     let run = cb.datetime(val);
                     if !run {
                         //dbg!(src.pos, &src.src[src.pos..], cb);
@@ -1074,6 +1544,32 @@ impl Tokenizer {
                     *state = States::Pop;
                 }
 
+                // Datetime in 3.0 layout inside of older message, see
+                // `lenient_datetime`
+                States::DateTimeVer30 => {
+                    let data = match self.buffer.read(14, &mut src) {
+                        Some(data) => data,
+                        None => {
+                            debug_assert!(src.is_all_consumed());
+                            return Ok((true, src.consumed()));
+                        }
+                    };
+
+                    let val = decode_datetime_v30(data);
+                    if !(1..=12).contains(&val.month) || val.timestamp().is_none() {
+                        cb.error("invalid datetime");
+                        return Err(src.pos);
+                    }
+
+                    let run = cb.datetime(val);
+                    if !run {
+                        cb.error("cb::datetime in Datetime failed");
+                        return Err(src.pos);
+                    }
+
+                    *state = States::Pop;
+                }
+
                 States::Pop => {
                     self.buffer.reset();
                     self.stack.pop();
@@ -1082,13 +1578,50 @@ impl Tokenizer {
                     //  again  when stack is empty
                     // Fault put to 2 values to stack so we dont have to care
                     if self.stack.is_empty() {
-                        if let Context::Call { args:_ } = self.context {
-                            self.stack.push(States::Value)
+                        match self.context {
+                            Context::Call { args: _ } => self.stack.push(States::Value),
+                            Context::Response => {
+                                self.message_ended = true;
+                                if !cb.message_end() {
+                                    cb.error("cb::message_end in Pop failed");
+                                    return Err(src.pos);
+                                }
+
+                                // Response is complete, keep Finish state to skip padding
+                                if self.tolerate_trailing_zeros {
+                                    self.stack.push(States::Finish)
+                                }
+                            }
+                            _ => {}
                         }
                     }
                 }
 
+                States::Skip { remaining } => {
+                    let cnt = cmp::min(*remaining, src.available());
+                    src.advance(cnt);
+                    *remaining -= cnt;
+                    if *remaining > 0 {
+                        return Ok((true, src.consumed()));
+                    }
+                    *state = States::Pop;
+                }
+
                 States::Finish => {
+                    if !self.message_ended {
+                        self.message_ended = true;
+                        if !cb.message_end() {
+                            cb.error("cb::message_end in Finish failed");
+                            return Err(src.pos);
+                        }
+                    }
+
+                    if self.tolerate_trailing_zeros {
+                        while !src.is_all_consumed() && src.data(1)[0] == 0 {
+                            src.advance(1);
+                        }
+                    }
+
                     // Detect calling tokenizer after it returned not needed data
                     // and there are unexpected data in source stream
                     if !src.is_all_consumed() {
@@ -1103,19 +1636,16 @@ impl Tokenizer {
                 States::DataInit => {
                     // first byte is data type or fault
                     if !self.buffer.consume(1, &mut src) {
-                        assert!(src.is_all_consumed());
+                        debug_assert!(src.is_all_consumed());
                         // data is optional so we dont need data
                         return Ok((false, src.consumed()));
                     }
 
                     match self.buffer.data[0] & TYPE_MASK {
                         FRPS_DATA_ID => {
-                            let octects: usize = match self.buffer.data[0] & OCTET_CNT_MASK {
-                                0 => 0,
-                                1 => 2,
-                                2 => 4,
-                                4 => 8,
-                                _ => {
+                            let octects = match data_len_octets(self.buffer.data[0]) {
+                                Some(v) => v,
+                                None => {
                                     cb.error("invalid type");
                                     return Err(src.pos);
                                 }
@@ -1137,11 +1667,22 @@ impl Tokenizer {
                 States::DataLen { octects } => {
                     // read array len
                     if !self.buffer.consume(*octects, &mut src) {
-                        assert!(src.is_all_consumed());
+                        debug_assert!(src.is_all_consumed());
                         return Ok((true, src.consumed()));
                     }
 
-                    let length = read_i64(&self.buffer.data[0..*octects]) as usize;
+                    let length = match read_len(&self.buffer.data[0..*octects]) {
+                        Some(v) => v,
+                        None => {
+                            cb.error("length out of range");
+                            return Err(src.pos);
+                        }
+                    };
+
+                    if !cb.stream_data_begin(length) {
+                        cb.error("cb::stream_data_begin in DataLen failed");
+                        return Err(src.pos);
+                    }
 
                     *state = States::Data {
                         length,
@@ -1152,14 +1693,15 @@ impl Tokenizer {
                 }
 
                 States::Data { length, processed } => {
-                    assert!(*processed <= *length, "invalid state");
+                    debug_assert!(*processed <= *length, "invalid state");
                     // Do we have any stream data and is not 0 length?
                     if (*processed != *length) && src.is_all_consumed() {
                         return Ok((true, src.consumed()));
                     }
 
                     // Process available or missing part
-                    let cnt = cmp::min(src.available(), *length - *processed);
+                    let cnt =
+                        cmp::min(src.available(), *length - *processed).min(self.max_chunk_size);
                     let run = cb.stream_data(src.data(cnt));
                     if !run {
                         //dbg!(src.pos, &src.src[src.pos..], cb);
@@ -1171,23 +1713,113 @@ impl Tokenizer {
                     src.advance(cnt);
                     *processed += cnt;
 
-                    // did we process all stream data?
+                    // did we process all stream data? Rest of input is passed
+                    // in next chunk
                     if processed != length {
-                        assert!(src.is_all_consumed());
-                        return Ok((true, src.consumed()));
+                        continue;
                     }
 
                     *state = States::Value;
+
+                    // Standalone data block (see `new_frps_data`) ends here
+                    if self.data_only {
+                        self.stack.pop();
+                    }
                 }
             }
         }
 
         // if !src.is_all_consumed() {
         //     dbg!(src.pos, &src.src[src.pos..], cb);
-        //     assert!(src.is_all_consumed());
+        //     debug_assert!(src.is_all_consumed());
         // }
         Ok((false, src.consumed()))
     }
+
+    /// Same as `parse` but tokenize data from `buf` cursor which is advanced
+    /// by processed bytes. Non contiguous buffers are tokenized chunk by
+    /// chunk. Error position is counted from the cursor at call time.
+    #[cfg(feature = "bytes")]
+    pub fn parse_buf<B: bytes::Buf, T: Callback + Debug>(
+        &mut self,
+        buf: &mut B,
+        cb: &mut T,
+    ) -> Result<(bool, usize), usize> {
+        let mut total = 0;
+        loop {
+            let chunk = buf.bytes();
+            let len = chunk.len();
+            let (need_data, processed) = self.parse(chunk, cb).map_err(|pos| total + pos)?;
+
+            buf.advance(processed);
+            total += processed;
+            if len == 0 || processed < len || !buf.has_remaining() {
+                return Ok((need_data, total));
+            }
+        }
+    }
+}
+
+/// Decode datetime of protocol 3.0 (without type byte)
+fn decode_datetime_v30(data: &[u8]) -> DateTimeVer30 {
+    // struct DateTimeFormat3_t {
+    //     uint8_t timeZone : 8;
+    //     int64_t unixTime : 64;
+    //     uint8_t weekDay : 3;
+    //     uint8_t sec : 6;
+    //     uint8_t minute : 6;
+    //     uint8_t hour : 5;
+    //     uint8_t day : 5;
+    //     uint8_t month : 4;
+    //     uint16_t year : 11;
+    // } __attribute__((packed));
+    DateTimeVer30 {
+        // zone is signed, it has to be sign extended
+        time_zone: data[0] as i8,
+        unix_time: LittleEndian::read_i64(&data[1..]),
+        week_day: data[9] & 0x07,
+        sec: ((data[9] & 0xf8) >> 3) | ((data[10] & 0x01) << 5),
+        min: (data[10] & 0x7e) >> 1,
+        hour: ((data[10] & 0x80) >> 7) | ((data[11] & 0x0f) << 1),
+        day: ((data[11] & 0xf0) >> 4) | ((data[12] & 0x01) << 4),
+        month: (data[12] & 0x1e) >> 1,
+        year: ((((data[12] as u16) & 0xe0) >> 5) | ((data[13] as u16) << 3)) + 1600,
+    }
+}
+
+/// Decode datetime of protocol 1.0 and 2.x (without type byte)
+fn decode_datetime_v10(data: &[u8]) -> DateTimeVer30 {
+    // struct DateTimeFormat1_t {
+    //     uint8_t timeZone : 8;
+    //     int32_t unixTime : 32;
+    //     uint8_t weekDay : 3;
+    //     uint8_t sec : 6;
+    //     uint8_t minute : 6;
+    //     uint8_t hour : 5;
+    //     uint8_t day : 5;
+    //     uint8_t month : 4;
+    //     uint16_t year : 11;
+    // } __attribute__((packed));
+    DateTimeVer30 {
+        time_zone: data[0] as i8,
+        unix_time: LittleEndian::read_i32(&data[1..]) as i64,
+        week_day: data[5] & 0x07,
+        sec: ((data[5] & 0xf8) >> 3) | ((data[6] & 0x01) << 5),
+        min: (data[6] & 0x7e) >> 1,
+        hour: ((data[6] & 0x80) >> 7) | ((data[7] & 0x0f) << 1),
+        day: ((data[7] & 0xf0) >> 4) | ((data[8] & 0x01) << 4),
+        month: (data[8] & 0x1e) >> 1,
+        year: ((((data[8] as u16) & 0xe0) >> 5) | ((data[9] as u16) << 3)) + 1600,
+    }
+}
+
+/// Return false when `count` items of at least `min_size` bytes can't fit
+/// into rest of input with `input_length` after position `pos`
+fn fits_input(input_length: Option<usize>, pos: usize, count: usize, min_size: usize) -> bool {
+    match input_length {
+        Some(length) => count.saturating_mul(min_size) <= length.saturating_sub(pos),
+        None => true,
+    }
 }
 
 /// Read i64 integer from slice with variable number of bytes betwwen 1 to 8
@@ -1203,101 +1835,801 @@ fn read_i64(s: &[u8]) -> i64 {
     i64::from_le_bytes(tmp)
 }
 
-/*
-   Decodes signed integer from unsigned,
-   with positive values even and negative values odd
-   starting around zero.
-   This saves transfer space and unifies integer encoding.
-   * -1 -> -9223372036854775808
-   *  0 -> 0
-   *  1 -> -1
-   *  2 -> 1
-   *  3 -> -2
-   *  4 -> 2
-   * ...
-   a = -9223372036854775808 Min i64
-   1000000000000000000000000000000000000000000000000000000000000000
-   a << 1
-   0000000000000000000000000000000000000000000000000000000000000000
-   a >> 63
-   1111111111111111111111111111111111111111111111111111111111111111
-   b = encoded(a)
-   1111111111111111111111111111111111111111111111111111111111111111
-   signed(b) >> 1
-   1111111111111111111111111111111111111111111111111111111111111111
-   unsigned(b) >> 1
-   0111111111111111111111111111111111111111111111111111111111111111
-   -(b & 1))
-   1111111111111111111111111111111111111111111111111111111111111111
-   c = decoded(b)
-   1000000000000000000000000000000000000000000000000000000000000000
-
-   a = -1
-   1111111111111111111111111111111111111111111111111111111111111111
-   a << 1
-   1111111111111111111111111111111111111111111111111111111111111110
-   a >> 63
-   1111111111111111111111111111111111111111111111111111111111111111
-   b = encoded(a)
-   0000000000000000000000000000000000000000000000000000000000000001
-   signed(b) >> 1
-   0000000000000000000000000000000000000000000000000000000000000000
-   unsigned(b) >> 1
-   0000000000000000000000000000000000000000000000000000000000000000
-   -(b & 1))
-   1111111111111111111111111111111111111111111111111111111111111111
-   c = decoded(b)
-   1111111111111111111111111111111111111111111111111111111111111111
-
-   a = 1
-   0000000000000000000000000000000000000000000000000000000000000001
-   a << 1
-   0000000000000000000000000000000000000000000000000000000000000010
-   a >> 63
-   0000000000000000000000000000000000000000000000000000000000000000
-   b = encoded(a)
-   0000000000000000000000000000000000000000000000000000000000000010
-   signed(b) >> 1
-   0000000000000000000000000000000000000000000000000000000000000001
-   unsigned(b) >> 1
-   0000000000000000000000000000000000000000000000000000000000000001
-   -(b & 1))
-   0000000000000000000000000000000000000000000000000000000000000000
-   c = decoded(b)
-   0000000000000000000000000000000000000000000000000000000000000001
-
-   a = 9223372036854775807 - Max i64
-   0111111111111111111111111111111111111111111111111111111111111111
-   a << 1
-   1111111111111111111111111111111111111111111111111111111111111110
-   a >> 63
-   0000000000000000000000000000000000000000000000000000000000000000
-   b = encoded(a)
-   1111111111111111111111111111111111111111111111111111111111111110
-   signed(b) >> 1
-   1111111111111111111111111111111111111111111111111111111111111111
-   unsigned(b) >> 1
-   0111111111111111111111111111111111111111111111111111111111111111
-   -(b & 1))
-   0000000000000000000000000000000000000000000000000000000000000000
-   c = decoded(b)
-   0111111111111111111111111111111111111111111111111111111111111111
-
-   int64_t encode(int64_t n) {
-       return ((n << 1) ^ (n >> 63));
-   }
-   static int64_t decode(int64_t s) {
-       uint64_t n = static_cast<uint64_t>(s);
-       return static_cast<int64_t>((n >> 1) ^ (-(s & 1)));
-   }
-*/
-
-fn zigzag_decode(s: &[u8]) -> i64 {
-    let unsigned_shr = |n: i64| {
-        let n = u64::from_le_bytes(n.to_le_bytes()) >> 1;
-        i64::from_le_bytes(n.to_le_bytes())
-    };
-
-    let s = read_i64(s);
-    unsigned_shr(s) ^ (-(s & 1))
+/// Read length, `None` when it doesn't fit in `usize` (on 32 bit targets)
+fn read_len(s: &[u8]) -> Option<usize> {
+    usize::try_from(read_i64(s) as u64).ok()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{ParsedStatus, Serializer, Value, ValueTreeBuilder};
+    use enum_extract::let_extract;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn trailing_zeros() {
+        // response 1 followed by block padding
+        let data = hex::decode("ca110300700802").unwrap();
+        let mut padded = data.clone();
+        padded.extend_from_slice(&[0, 0, 0, 0]);
+
+        let mut tokenizer = Tokenizer::new_frpc();
+        let mut tree = ValueTreeBuilder::new();
+        assert_eq!(tokenizer.parse(&padded, &mut tree), Ok((false, data.len())));
+
+        let mut tokenizer = Tokenizer::new_frpc().tolerate_trailing_zeros(true);
+        let mut tree = ValueTreeBuilder::new();
+        assert_eq!(
+            tokenizer.parse(&padded, &mut tree),
+            Ok((false, padded.len()))
+        );
+        assert_eq!(tree.to_string(), "1");
+
+        // padding fed by one byte
+        let mut tokenizer = Tokenizer::new_frpc().tolerate_trailing_zeros(true);
+        let mut tree = ValueTreeBuilder::new();
+        for b in padded.chunks(1) {
+            assert!(tokenizer.parse(b, &mut tree).is_ok());
+        }
+        assert_eq!(tree.to_string(), "1");
+
+        // call echo(1) followed by padding
+        let call = hex::decode("ca11030068046563686f080200000000").unwrap();
+        let mut tokenizer = Tokenizer::new_frpc().tolerate_trailing_zeros(true);
+        let mut tree = ValueTreeBuilder::new();
+        assert_eq!(tokenizer.parse(&call, &mut tree), Ok((false, call.len())));
+        assert_eq!(tree.to_string(), "echo(1)");
+
+        // non-zero data after padding is still error
+        padded.push(1);
+        let mut tokenizer = Tokenizer::new_frpc().tolerate_trailing_zeros(true);
+        let mut tree = ValueTreeBuilder::new();
+        assert!(tokenizer.parse(&padded, &mut tree).is_err());
+        assert_eq!(tree.to_string(), "error(data after end)");
+    }
+
+    #[test]
+    fn data_only_reset() {
+        // data block "abc" with 2 octets of length
+        let data = hex::decode("010300616263").unwrap();
+        let mut tokenizer = Tokenizer::new_frps_data();
+        for _ in 0..2 {
+            let mut tree = ValueTreeBuilder::new();
+            assert_eq!(tokenizer.parse(&data, &mut tree), Ok((false, data.len())));
+            assert_eq!(tokenizer.state(), TokenizerState::Complete);
+            assert_eq!(tree.data, b"abc".to_vec());
+            tokenizer.reset();
+        }
+
+        // message header is not expected after reset
+        let mut tree = ValueTreeBuilder::new();
+        assert!(tokenizer
+            .parse(&hex::decode("ca11").unwrap(), &mut tree)
+            .is_err());
+    }
+
+    #[test]
+    fn trailing_zeros_frps() {
+        // response 1 followed by padding, zeros are empty data blocks
+        let data = hex::decode("ca1102017038010000").unwrap();
+        for tolerate in &[false, true] {
+            let mut tokenizer = Tokenizer::new_frps().tolerate_trailing_zeros(*tolerate);
+            let mut tree = ValueTreeBuilder::new();
+            assert_eq!(tokenizer.parse(&data, &mut tree), Ok((false, data.len())));
+            assert_eq!(tree.to_string(), "1");
+            assert!(tree.data.is_empty());
+        }
+
+        // fault(500, "x") followed by padding
+        let data = hex::decode("ca1102017839f4012001780000").unwrap();
+        let mut tokenizer = Tokenizer::new_frps();
+        let mut tree = ValueTreeBuilder::new();
+        assert!(tokenizer.parse(&data, &mut tree).is_err());
+
+        let mut tokenizer = Tokenizer::new_frps().tolerate_trailing_zeros(true);
+        let mut tree = ValueTreeBuilder::new();
+        assert_eq!(tokenizer.parse(&data, &mut tree), Ok((false, data.len())));
+        assert_eq!(tree.to_string(), "fault(500, \"x\")");
+    }
+
+    #[test]
+    fn struct_key_split_on_every_byte() {
+        // response {status: 200}
+        let mut data = hex::decode("ca110300705001").unwrap();
+        data.push(6);
+        data.extend_from_slice(b"status");
+        data.extend_from_slice(&[0x09, 0x90, 0x01]);
+        let key_end = 8 + 6;
+
+        for split in 1..data.len() {
+            let mut tokenizer = Tokenizer::new_frpc();
+            let mut tree = ValueTreeBuilder::new();
+            assert_eq!(
+                tokenizer.parse(&data[..split], &mut tree),
+                Ok((true, split))
+            );
+            assert_eq!(
+                tokenizer.parse(&data[split..], &mut tree),
+                Ok((false, data.len() - split))
+            );
+            assert_eq!(tree.to_string(), "{status: 200}", "split at {}", split);
+        }
+
+        // key split exactly before its final byte, fed by one byte after
+        let mut tokenizer = Tokenizer::new_frpc();
+        let mut tree = ValueTreeBuilder::new();
+        assert!(tokenizer.parse(&data[..key_end - 1], &mut tree).is_ok());
+        for b in data[key_end - 1..].chunks(1) {
+            assert!(tokenizer.parse(b, &mut tree).is_ok());
+        }
+        assert_eq!(tree.to_string(), "{status: 200}");
+    }
+
+    #[test]
+    fn fixed_size_fields_split_on_every_byte() {
+        let value = Value::Array(vec![
+            Value::Int(-1_000_000),
+            Value::Double(0.5),
+            Value::datetime_from_unix(1_500_000_000),
+        ]);
+        let mut data = [0; 64];
+        let mut serializer = Serializer::new();
+        let len = serializer.write_response(&mut data, &value).unwrap();
+        let data = &data[..len];
+
+        // contiguous data are read directly from source
+        let mut tokenizer = Tokenizer::new_frpc();
+        let mut tree = ValueTreeBuilder::new();
+        assert_eq!(tokenizer.parse(data, &mut tree), Ok((false, len)));
+        assert_eq!(tree.values, vec![value]);
+        let expected = tree.to_string();
+
+        for split in 1..len {
+            let mut tokenizer = Tokenizer::new_frpc();
+            let mut tree = ValueTreeBuilder::new();
+            assert_eq!(
+                tokenizer.parse(&data[..split], &mut tree),
+                Ok((true, split))
+            );
+            assert_eq!(
+                tokenizer.parse(&data[split..], &mut tree),
+                Ok((false, len - split))
+            );
+            assert_eq!(tree.to_string(), expected, "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn strict_reserved_bits() {
+        // response 1.5 with non-zero additional type info of double
+        let data = hex::decode("ca1103007019000000000000f83f").unwrap();
+
+        let mut tokenizer = Tokenizer::new_frpc();
+        let mut tree = ValueTreeBuilder::new();
+        assert_eq!(tokenizer.parse(&data, &mut tree), Ok((false, data.len())));
+        assert_eq!(tree.to_string(), "1.5");
+
+        let mut tokenizer = Tokenizer::new_frpc().strict(true);
+        let mut tree = ValueTreeBuilder::new();
+        assert_eq!(tokenizer.parse(&data, &mut tree), Err(6));
+        assert_eq!(tree.to_string(), "error(non-zero reserved bits)");
+
+        // response type itself
+        let data = hex::decode("ca11030071").unwrap();
+        let mut tokenizer = Tokenizer::new_frpc().strict(true);
+        let mut tree = ValueTreeBuilder::new();
+        assert_eq!(tokenizer.parse(&data, &mut tree), Err(5));
+    }
+
+    #[test]
+    fn debug_state_of_partial_call() {
+        let mut tokenizer = Tokenizer::new_frpc();
+        let mut tree = ValueTreeBuilder::new();
+        assert_eq!(tokenizer.debug_state(), "Init");
+
+        assert_eq!(tokenizer.parse(&[0xca, 0x11], &mut tree), Ok((true, 2)));
+        assert_eq!(tokenizer.debug_state(), "Init");
+
+        // method "test" with only 2 characters
+        let data = hex::decode("0300680474").unwrap();
+        assert_eq!(tokenizer.parse(&data, &mut tree), Ok((true, 5)));
+        assert_eq!(tokenizer.parse(b"e", &mut tree), Ok((true, 1)));
+        assert_eq!(
+            tokenizer.debug_state(),
+            "CallName { length: 4, processed: 2 }"
+        );
+    }
+
+    // value tree builder counting tokens, shared by tests of other
+    // callbacks
+    #[derive(Debug)]
+    pub(crate) struct Probe {
+        pub(crate) tree: ValueTreeBuilder,
+        pub(crate) tokens: usize,
+        pub(crate) message_ends: usize,
+        // older versions are rejected
+        min_major_version: u8,
+    }
+
+    impl Probe {
+        pub(crate) fn new(min_major_version: u8) -> Probe {
+            Probe {
+                tree: ValueTreeBuilder::new(),
+                tokens: 0,
+                message_ends: 0,
+                min_major_version,
+            }
+        }
+
+        fn count(&mut self) -> bool {
+            self.tokens += 1;
+            true
+        }
+    }
+
+    impl Callback for Probe {
+        fn error(&mut self, msg: &str) {
+            self.tree.error(msg)
+        }
+        fn version(&mut self, major_version: u8, minor_version: u8) -> bool {
+            self.count()
+                && major_version >= self.min_major_version
+                && self.tree.version(major_version, minor_version)
+        }
+        fn call(&mut self, method: &str, length: usize) -> bool {
+            self.count() && self.tree.call(method, length)
+        }
+        fn response(&mut self) -> bool {
+            self.count() && self.tree.response()
+        }
+        fn fault(&mut self) -> bool {
+            self.count() && self.tree.fault()
+        }
+        fn stream_data(&mut self, v: &[u8]) -> bool {
+            self.count() && self.tree.stream_data(v)
+        }
+        fn null(&mut self) -> bool {
+            self.count() && self.tree.null()
+        }
+        fn integer(&mut self, v: i64) -> bool {
+            self.count() && self.tree.integer(v)
+        }
+        fn boolean(&mut self, v: bool) -> bool {
+            self.count() && self.tree.boolean(v)
+        }
+        fn double_number(&mut self, v: f64) -> bool {
+            self.count() && self.tree.double_number(v)
+        }
+        fn datetime(&mut self, v: DateTimeVer30) -> bool {
+            self.count() && self.tree.datetime(v)
+        }
+        fn string_begin(&mut self, len: usize) -> bool {
+            self.count() && self.tree.string_begin(len)
+        }
+        fn string_data(&mut self, v: &[u8], len: usize) -> bool {
+            self.count() && self.tree.string_data(v, len)
+        }
+        fn binary_begin(&mut self, len: usize) -> bool {
+            self.count() && self.tree.binary_begin(len)
+        }
+        fn binary_data(&mut self, v: &[u8], len: usize) -> bool {
+            self.count() && self.tree.binary_data(v, len)
+        }
+        fn array_begin(&mut self, len: usize) -> bool {
+            self.count() && self.tree.array_begin(len)
+        }
+        fn struct_begin(&mut self, len: usize) -> bool {
+            self.count() && self.tree.struct_begin(len)
+        }
+        fn struct_key(&mut self, v: &[u8], len: usize) -> bool {
+            self.count() && self.tree.struct_key(v, len)
+        }
+        fn value_end(&mut self) -> bool {
+            self.count() && self.tree.value_end()
+        }
+        fn message_end(&mut self) -> bool {
+            self.message_ends += 1;
+            true
+        }
+        // pretend 0x80 type carries 3 bytes of payload
+        fn unknown_type(&mut self, tag: u8) -> Option<usize> {
+            if tag == 0x80 {
+                Some(3)
+            } else {
+                None
+            }
+        }
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let data = hex::decode("ca1101007020016b").unwrap();
+        let mut tokenizer = Tokenizer::new_frpc();
+        let mut probe = Probe::new(2);
+        assert_eq!(tokenizer.parse(&data, &mut probe), Err(4));
+        assert_eq!(
+            probe.tree.what,
+            ParsedStatus::Error("unsupported version 1.0".to_owned())
+        );
+        assert_eq!(tokenizer.version(), Some(Version::V10));
+
+        // version below configured minimum
+        let mut tokenizer = Tokenizer::new_frpc().min_version(Version::V20);
+        let mut tree = ValueTreeBuilder::new();
+        assert_eq!(tokenizer.parse(&data, &mut tree), Err(4));
+        assert_eq!(
+            tree.what,
+            ParsedStatus::Error("unsupported version 1.0".to_owned())
+        );
+
+        // minimum is kept by reset
+        tokenizer.reset();
+        let data = hex::decode("ca1102017020016b").unwrap();
+        let mut tree = ValueTreeBuilder::new();
+        assert_eq!(tokenizer.parse(&data, &mut tree), Ok((false, data.len())));
+        assert_eq!(tree.values, vec![Value::Str("k".to_owned())]);
+
+        // unknown version is malformed message, callback is not asked
+        let data = hex::decode("ca1104007020016b").unwrap();
+        let mut tokenizer = Tokenizer::new_frpc();
+        let mut tree = ValueTreeBuilder::new();
+        assert_eq!(tokenizer.version(), None);
+        assert_eq!(tokenizer.parse(&data, &mut tree), Err(4));
+        assert_eq!(
+            tree.what,
+            ParsedStatus::Error("bad protocol version".to_owned())
+        );
+    }
+
+    #[test]
+    fn unknown_type() {
+        // response [1, <reserved type 0x80>]
+        let data = hex::decode("ca110300705802080280").unwrap();
+        let mut tokenizer = Tokenizer::new_frpc();
+        let mut tree = ValueTreeBuilder::new();
+        assert_eq!(tokenizer.parse(&data, &mut tree), Err(data.len()));
+        assert_eq!(
+            tree.what,
+            ParsedStatus::Error("unknown type 0x80 at 9".to_owned())
+        );
+
+        // position is same when message comes in chunks
+        let mut tokenizer = Tokenizer::new_frpc();
+        let mut tree = ValueTreeBuilder::new();
+        assert_eq!(tokenizer.parse(&data[..7], &mut tree), Ok((true, 7)));
+        assert_eq!(tokenizer.parse(&data[7..], &mut tree), Err(3));
+        assert_eq!(
+            tree.what,
+            ParsedStatus::Error("unknown type 0x80 at 9".to_owned())
+        );
+
+        // callback knows how to skip it
+        let data = hex::decode("ca110300705802080280aabbcc").unwrap();
+        let mut tokenizer = Tokenizer::new_frpc();
+        let mut skip = Probe::new(0);
+        assert_eq!(tokenizer.parse(&data[..11], &mut skip), Ok((true, 11)));
+        assert_eq!(tokenizer.parse(&data[11..], &mut skip), Ok((false, 2)));
+        assert_eq!(skip.tree.values, vec![Value::Array(vec![Value::Int(1)])]);
+    }
+
+    #[test]
+    fn message_end_once() {
+        let mut probe = Probe::new(0);
+
+        // response "ab" fed byte by byte, fires when last byte is processed
+        let data = hex::decode("ca11030070200261").unwrap();
+        let mut tokenizer = Tokenizer::new_frpc().tolerate_trailing_zeros(true);
+        for b in data.chunks(1) {
+            assert_eq!(tokenizer.parse(b, &mut probe), Ok((true, 1)));
+        }
+        assert_eq!(probe.message_ends, 0);
+        assert_eq!(tokenizer.parse(b"b", &mut probe), Ok((false, 1)));
+        assert_eq!(tokenizer.parse(&[0, 0], &mut probe), Ok((false, 2)));
+        assert_eq!(probe.message_ends, 1);
+
+        // fault(1, "")
+        let data = hex::decode("ca11030078080220").unwrap();
+        let mut tokenizer = Tokenizer::new_frpc();
+        assert_eq!(tokenizer.parse(&data, &mut probe), Ok((true, 8)));
+        assert_eq!(probe.message_ends, 1);
+        assert_eq!(tokenizer.parse(&[0], &mut probe), Ok((false, 1)));
+        assert_eq!(probe.message_ends, 2);
+
+        // call arguments are not terminated
+        let data = hex::decode("ca110300680161").unwrap();
+        let mut tokenizer = Tokenizer::new_frpc();
+        assert_eq!(tokenizer.parse(&data, &mut probe), Ok((false, 7)));
+        assert_eq!(probe.message_ends, 2);
+    }
+
+    #[test]
+    fn framing_state_of_call() {
+        let mut tokenizer = Tokenizer::new_frpc();
+        let mut tree = ValueTreeBuilder::new();
+        assert_eq!(tokenizer.state(), TokenizerState::Header);
+
+        let mut feed = |tokenizer: &mut Tokenizer, data: &str| {
+            let data = hex::decode(data).unwrap();
+            let _ = tokenizer.parse(&data, &mut tree);
+            tokenizer.state()
+        };
+
+        // m("a", "b")
+        assert_eq!(feed(&mut tokenizer, "ca11"), TokenizerState::Header);
+        assert_eq!(feed(&mut tokenizer, "0300"), TokenizerState::Header);
+        assert_eq!(feed(&mut tokenizer, "6801"), TokenizerState::Header);
+        let state = feed(&mut tokenizer, "6d");
+        assert_eq!(state, TokenizerState::ExpectingOptionalArgs);
+        assert_eq!(feed(&mut tokenizer, "2001"), TokenizerState::InValue);
+        let state = feed(&mut tokenizer, "61");
+        assert_eq!(state, TokenizerState::ExpectingOptionalArgs);
+        assert_eq!(feed(&mut tokenizer, "20"), TokenizerState::InValue);
+        let state = feed(&mut tokenizer, "0162");
+        assert_eq!(state, TokenizerState::ExpectingOptionalArgs);
+        assert_eq!(feed(&mut tokenizer, "80"), TokenizerState::Error);
+
+        tokenizer.reset();
+        assert_eq!(tokenizer.state(), TokenizerState::Header);
+
+        // response "a"
+        let state = feed(&mut tokenizer, "ca11030070200161");
+        assert_eq!(state, TokenizerState::Complete);
+    }
+
+    #[test]
+    fn framing_state_of_response_header() {
+        let mut tree = ValueTreeBuilder::new();
+        let header = hex::decode("ca11030070").unwrap();
+        let value = hex::decode("200161").unwrap();
+
+        let mut tokenizer = Tokenizer::new_frpc();
+        assert_eq!(tokenizer.parse(&header, &mut tree), Ok((true, 5)));
+        assert_eq!(tokenizer.state(), TokenizerState::InValue);
+        assert_eq!(tokenizer.parse(&value, &mut tree), Ok((false, 3)));
+        assert_eq!(tokenizer.state(), TokenizerState::Complete);
+
+        // FRPS data may follow response value
+        let mut tokenizer = Tokenizer::new_frps();
+        assert_eq!(tokenizer.parse(&header, &mut tree), Ok((true, 5)));
+        assert_eq!(tokenizer.state(), TokenizerState::InValue);
+        assert!(tokenizer.parse(&value, &mut tree).is_ok());
+        assert_eq!(tokenizer.state(), TokenizerState::ExpectingOptionalArgs);
+    }
+
+    #[test]
+    fn call_args_limit() {
+        // m() with 10 000 null arguments
+        let mut data = hex::decode("ca110300680161").unwrap();
+        data.resize(data.len() + 10_000, NULL_ID);
+
+        let mut tree = ValueTreeBuilder::new();
+        let mut tokenizer = Tokenizer::new_frpc().max_call_args(1_000);
+        assert_eq!(tokenizer.parse(&data, &mut tree), Err(7 + 1_001));
+        assert_eq!(
+            tree.what,
+            ParsedStatus::Error("too many call arguments".to_owned())
+        );
+
+        // nested values are not counted
+        let data = hex::decode("ca110300680161580360606060").unwrap();
+        let mut tree = ValueTreeBuilder::new();
+        let mut tokenizer = Tokenizer::new_frpc().max_call_args(2);
+        assert_eq!(tokenizer.parse(&data, &mut tree), Ok((false, data.len())));
+        assert_eq!(tree.values.len(), 2);
+    }
+
+    #[test]
+    fn datetime_v30_in_v21_message() {
+        // 2017-01-27 12:39:19 in 3.0 layout in 2.1 response
+        let data = hex::decode("ca110201702800773f8b58000000009d4eb62334").unwrap();
+
+        let mut tree = ValueTreeBuilder::new();
+        let mut tokenizer = Tokenizer::new_frpc();
+        assert!(tokenizer.parse(&data, &mut tree).is_err());
+        assert_eq!(
+            tree.what,
+            ParsedStatus::Error("invalid datetime".to_owned())
+        );
+
+        let mut tree = ValueTreeBuilder::new();
+        let mut tokenizer = Tokenizer::new_frpc().lenient_datetime(true);
+        assert_eq!(tokenizer.parse(&data, &mut tree), Ok((false, data.len())));
+        let_extract!(Value::DateTime(v), &tree.values[0], unreachable!());
+        assert_eq!(v.timestamp(), Some(1485520759));
+
+        // datetime in declared layout is not affected
+        let data = hex::decode("ca110201702800773f8b589d4eb62334").unwrap();
+        let mut tree = ValueTreeBuilder::new();
+        let mut tokenizer = Tokenizer::new_frpc().lenient_datetime(true);
+        assert_eq!(tokenizer.parse(&data, &mut tree), Ok((false, data.len())));
+        assert_eq!(tree.values[0].to_string(), "2017-01-27 12:39:19");
+    }
+
+    #[test]
+    fn datetime_v30_before_epoch() {
+        // 1969-07-20 20:17:40, timestamp is signed
+        let data = hex::decode("ca110300702800e49527ffffffffff40234a2f2e").unwrap();
+        let mut tree = ValueTreeBuilder::new();
+        let mut tokenizer = Tokenizer::new_frpc();
+        assert_eq!(tokenizer.parse(&data, &mut tree), Ok((false, data.len())));
+        let_extract!(Value::DateTime(v), &tree.values[0], unreachable!());
+        assert_eq!(v.unix_time, -14182940);
+        assert_eq!((v.year, v.month, v.day, v.hour), (1969, 7, 20, 20));
+        assert_eq!(tree.values[0].to_string(), "1969-07-20 20:17:40");
+    }
+
+    #[test]
+    fn max_chunk_size() {
+        // response with 10 KB binary
+        let mut data = hex::decode("ca11030070310028").unwrap();
+        data.extend((0..10240).map(|i| i as u8));
+
+        let chunks = Arc::new(Mutex::new(vec![]));
+        let log = chunks.clone();
+        let mut tree =
+            ValueTreeBuilder::new().with_progress(move |done, _| log.lock().unwrap().push(done));
+        let mut tokenizer = Tokenizer::new_frpc().max_chunk_size(1024);
+        assert_eq!(tokenizer.parse(&data, &mut tree), Ok((false, data.len())));
+        assert_eq!(tree.values[0], Value::Binary(data[8..].to_vec()));
+        let expected: Vec<_> = (1..=10).map(|i| i * 1024).collect();
+        assert_eq!(*chunks.lock().unwrap(), expected);
+
+        // string split into chunks too
+        let data = hex::decode("ca110300702003616263").unwrap();
+        let mut tree = ValueTreeBuilder::new();
+        let mut tokenizer = Tokenizer::new_frpc().max_chunk_size(2);
+        assert_eq!(tokenizer.parse(&data, &mut tree), Ok((false, data.len())));
+        assert_eq!(tree.values[0], Value::Str("abc".to_owned()));
+    }
+
+    #[test]
+    fn skip_prefix() {
+        // 3 bytes of transport header before response "abc"
+        let data = hex::decode("0102ffca110300702003616263").unwrap();
+        let mut tokenizer = Tokenizer::new_frpc().skip_prefix(3);
+        let mut tree = ValueTreeBuilder::new();
+        for (i, byte) in data.iter().enumerate() {
+            if i < 3 {
+                assert_eq!(tokenizer.state(), TokenizerState::Header);
+            }
+            assert_eq!(
+                tokenizer.parse(&[*byte], &mut tree),
+                Ok((i + 1 < data.len(), 1))
+            );
+        }
+        assert_eq!(tree.values, vec![Value::Str("abc".to_owned())]);
+
+        // prefix is skipped again after reset
+        tokenizer.reset();
+        let mut tree = ValueTreeBuilder::new();
+        assert_eq!(tokenizer.parse(&data, &mut tree), Ok((false, data.len())));
+        assert_eq!(tree.values, vec![Value::Str("abc".to_owned())]);
+
+        // later call replaces prefix, before and after reset
+        let mut tokenizer = Tokenizer::new_frpc().skip_prefix(2).skip_prefix(3);
+        for _ in 0..2 {
+            let mut tree = ValueTreeBuilder::new();
+            assert_eq!(tokenizer.parse(&data, &mut tree), Ok((false, data.len())));
+            assert_eq!(tree.values, vec![Value::Str("abc".to_owned())]);
+            tokenizer.reset();
+        }
+
+        let mut tokenizer = Tokenizer::new_frpc().skip_prefix(3).skip_prefix(0);
+        let mut tree = ValueTreeBuilder::new();
+        assert_eq!(
+            tokenizer.parse(&data[3..], &mut tree),
+            Ok((false, data.len() - 3))
+        );
+    }
+
+    #[test]
+    fn datetime_v30_epoch_sentinel() {
+        // unix time -1, calendar fields 2017-01-27 12:39:19
+        let data = hex::decode("ca110300702800ffffffffffffffff9d4eb62334").unwrap();
+        let mut tree = ValueTreeBuilder::new();
+        let mut tokenizer = Tokenizer::new_frpc();
+        assert_eq!(tokenizer.parse(&data, &mut tree), Ok((false, data.len())));
+        let_extract!(Value::DateTime(v), &tree.values[0], unreachable!());
+        assert!(v.is_epoch_sentinel());
+        assert_eq!(v.timestamp(), Some(1485520759));
+        assert_eq!(tree.values[0].to_string(), "2017-01-27 12:39:19");
+
+        // calendar fields 2017-02-30 12:39:19 can't replace unix time
+        let data = hex::decode("ca110300702800ffffffffffffffff9d4ee62534").unwrap();
+        let mut tree = ValueTreeBuilder::new();
+        let mut tokenizer = Tokenizer::new_frpc();
+        assert_eq!(tokenizer.parse(&data, &mut tree), Err(data.len()));
+        assert_eq!(
+            tree.what,
+            ParsedStatus::Error("invalid datetime".to_owned())
+        );
+    }
+
+    #[test]
+    fn invalid_bool() {
+        // response with BOOL_ID | 0x02
+        let data = hex::decode("ca1103007012").unwrap();
+        let mut tree = ValueTreeBuilder::new();
+        let mut tokenizer = Tokenizer::new_frpc();
+        assert_eq!(tokenizer.parse(&data, &mut tree), Err(5));
+        assert_eq!(
+            tree.what,
+            ParsedStatus::Error("invalid boolean: additional info must be 0 or 1".to_owned())
+        );
+    }
+
+    // decode response with single integer
+    fn parse_int(data: &str) -> Result<Value, ParsedStatus> {
+        let data = hex::decode(data).unwrap();
+        let mut tree = ValueTreeBuilder::new();
+        match Tokenizer::new_frpc().parse(&data, &mut tree) {
+            Ok((false, _)) => Ok(tree.values.remove(0)),
+            _ => Err(tree.what),
+        }
+    }
+
+    #[test]
+    fn non_minimal_integers() {
+        // 5 and -5 in every octet count, encoders should use minimal one
+        for cnt in 1..=8 {
+            let bytes = format!("{:0<width$}", "", width = (cnt - 1) * 2);
+            let tag = cnt as u8 - 1;
+            let v30 = format!("ca11030070{:02x}0a{}", INT_ID | tag, bytes);
+            assert_eq!(parse_int(&v30), Ok(Value::Int(5)), "{}", v30);
+            let v21 = format!("ca11020170{:02x}05{}", U_VINT_ID | tag, bytes);
+            assert_eq!(parse_int(&v21), Ok(Value::Int(5)), "{}", v21);
+            let v21 = format!("ca11020170{:02x}05{}", VINT_ID | tag, bytes);
+            assert_eq!(parse_int(&v21), Ok(Value::Int(-5)), "{}", v21);
+        }
+
+        // 8 octets boundaries, magnitude is zero extended
+        assert_eq!(
+            parse_int("ca110201703fffffffffffffff7f"),
+            Ok(Value::Int(i64::MAX))
+        );
+        assert_eq!(
+            parse_int("ca11020170470000000000000080"),
+            Ok(Value::Int(i64::MIN))
+        );
+        assert_eq!(
+            parse_int("ca110201703f0000000000000080"),
+            Err(ParsedStatus::Error("integer out of range".to_owned()))
+        );
+        assert_eq!(parse_int("ca1102017038ff"), Ok(Value::Int(255)));
+        assert_eq!(
+            parse_int("ca110300700ffeffffffffffffff"),
+            Ok(Value::Int(i64::MAX))
+        );
+        assert_eq!(
+            parse_int("ca110300700fffffffffffffffff"),
+            Ok(Value::Int(i64::MIN))
+        );
+    }
+
+    #[test]
+    fn method_name_utf8() {
+        // "mě" with 2 bytes character split between chunks
+        let data = hex::decode("ca11030068036dc49b").unwrap();
+        for split in 5..data.len() {
+            let mut tree = ValueTreeBuilder::new();
+            let mut tokenizer = Tokenizer::new_frpc();
+            assert_eq!(
+                tokenizer.parse(&data[..split], &mut tree),
+                Ok((true, split))
+            );
+            let res = tokenizer.parse(&data[split..], &mut tree);
+            assert_eq!(res, Ok((false, data.len() - split)));
+            assert_eq!(tree.what, ParsedStatus::MethodCall("mě".to_owned()));
+        }
+
+        // invalid utf8 used to panic
+        let data = hex::decode("ca11030068026dff").unwrap();
+        let mut tree = ValueTreeBuilder::new();
+        let mut tokenizer = Tokenizer::new_frpc();
+        assert_eq!(tokenizer.parse(&data, &mut tree), Err(7));
+        assert_eq!(
+            tree.what,
+            ParsedStatus::Error("invalid utf8 in method name".to_owned())
+        );
+
+        // name ends in the middle of character
+        let data = hex::decode("ca11030068026dc4").unwrap();
+        for split in 5..data.len() {
+            let mut tree = ValueTreeBuilder::new();
+            let mut tokenizer = Tokenizer::new_frpc();
+            let res = tokenizer
+                .parse(&data[..split], &mut tree)
+                .and_then(|_| tokenizer.parse(&data[split..], &mut tree));
+            assert!(res.is_err(), "split {}", split);
+        }
+    }
+
+    #[test]
+    fn empty_input() {
+        // m(1, "ab", b"x", {k: (true, null, 1.5, datetime)})
+        let mut members = std::collections::HashMap::new();
+        let items = vec![
+            Value::Bool(true),
+            Value::Null,
+            Value::Double(1.5),
+            Value::datetime_from_unix(1_500_000_000),
+        ];
+        members.insert("k".to_owned(), Value::Array(items));
+        let args = vec![
+            Value::Int(1),
+            Value::Str("ab".to_owned()),
+            Value::Binary(b"x".to_vec()),
+            Value::Struct(members),
+        ];
+        let mut call = vec![];
+        Serializer::new().append_call(&mut call, "m", args).unwrap();
+        // response "x" with data "abc" in FRPS
+        let frps = hex::decode("ca11020170200178010300616263").unwrap();
+
+        let messages = [
+            (call, Tokenizer::new_frpc as fn() -> Tokenizer),
+            (frps, Tokenizer::new_frps),
+        ];
+        for (data, new_tokenizer) in messages.iter() {
+            let mut expected = ValueTreeBuilder::new();
+            let end = new_tokenizer().parse(data, &mut expected);
+
+            // empty slice from any state is no progress
+            for split in 0..=data.len() {
+                let mut tree = ValueTreeBuilder::new();
+                let mut tokenizer = new_tokenizer();
+                assert_eq!(tokenizer.parse(&[], &mut tree), Ok((true, 0)));
+                let (need_data, _) = tokenizer.parse(&data[..split], &mut tree).unwrap();
+                assert_eq!(
+                    tokenizer.parse(&[], &mut tree),
+                    Ok((need_data, 0)),
+                    "split {}",
+                    split
+                );
+                let res = tokenizer.parse(&data[split..], &mut tree);
+                assert_eq!(res.map(|r| r.0), end.map(|r| r.0), "split {}", split);
+                assert_eq!(tree.to_string(), expected.to_string());
+                assert_eq!(tree.data, expected.data);
+            }
+        }
+    }
+
+    #[test]
+    fn length_width() {
+        assert_eq!(read_len(&[0xff, 0xff, 0xff, 0xff]), Some(0xffff_ffff));
+        assert_eq!(read_len(&[0, 0, 0, 0, 1]), usize::try_from(1u64 << 32).ok());
+    }
+
+    #[cfg(target_pointer_width = "32")]
+    #[test]
+    fn length_above_u32() {
+        // string with 5 bytes length 0x100000001
+        let data = hex::decode("ca110300702401000000010061").unwrap();
+        let mut tree = ValueTreeBuilder::new();
+        assert_eq!(Tokenizer::new_frpc().parse(&data, &mut tree), Err(11));
+        assert_eq!(
+            tree.what,
+            ParsedStatus::Error("length out of range".to_owned())
+        );
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn parse_buf_chunks() {
+        use bytes::buf::{Buf, BufExt};
+
+        // response "abc" split into two fragments
+        let data = hex::decode("ca1103007020036162").unwrap();
+        let first = bytes::Bytes::copy_from_slice(&data);
+        let second = bytes::Bytes::from_static(b"c");
+
+        let mut tokenizer = Tokenizer::new_frpc();
+        let mut tree = ValueTreeBuilder::new();
+
+        let mut buf = first.chain(second);
+        assert_eq!(tokenizer.parse_buf(&mut buf, &mut tree), Ok((false, 10)));
+        assert!(!buf.has_remaining());
+        assert_eq!(tree.values, vec![Value::Str("abc".to_owned())]);
+    }
 }
@@ -0,0 +1,103 @@
+use crate::common::{MAX_BIN_LENGTH, MAX_STR_LENGTH};
+use crate::datetime::DateTimeVer30;
+use crate::tokenizer::Callback;
+use std::str;
+
+/// Callback accepting the same messages as `ValueTreeBuilder` without
+/// building values. Only strings and struct keys are kept until they can be
+/// checked for valid utf8.
+#[derive(Debug, Default)]
+pub(crate) struct Validator {
+    // string or struct key being received
+    chunks: Vec<u8>,
+    in_string: bool,
+}
+
+impl Callback for Validator {
+    fn error(&mut self, _msg: &str) {}
+
+    fn version(&mut self, _major_version: u8, _minor_version: u8) -> bool {
+        true
+    }
+
+    fn call(&mut self, _method: &str, _length: usize) -> bool {
+        true
+    }
+
+    fn response(&mut self) -> bool {
+        true
+    }
+
+    fn fault(&mut self) -> bool {
+        true
+    }
+
+    fn stream_data(&mut self, _v: &[u8]) -> bool {
+        true
+    }
+
+    fn null(&mut self) -> bool {
+        true
+    }
+
+    fn integer(&mut self, _v: i64) -> bool {
+        true
+    }
+
+    fn boolean(&mut self, _v: bool) -> bool {
+        true
+    }
+
+    fn double_number(&mut self, _v: f64) -> bool {
+        true
+    }
+
+    fn datetime(&mut self, _v: DateTimeVer30) -> bool {
+        true
+    }
+
+    fn string_begin(&mut self, len: usize) -> bool {
+        self.chunks.clear();
+        self.in_string = true;
+        len <= MAX_STR_LENGTH
+    }
+
+    fn string_data(&mut self, v: &[u8], _len: usize) -> bool {
+        self.chunks.extend_from_slice(v);
+        true
+    }
+
+    fn binary_begin(&mut self, len: usize) -> bool {
+        len <= MAX_BIN_LENGTH
+    }
+
+    fn binary_data(&mut self, _v: &[u8], _len: usize) -> bool {
+        true
+    }
+
+    fn array_begin(&mut self, _len: usize) -> bool {
+        true
+    }
+
+    fn struct_begin(&mut self, _len: usize) -> bool {
+        true
+    }
+
+    fn struct_key(&mut self, v: &[u8], len: usize) -> bool {
+        self.chunks.extend_from_slice(v);
+        if self.chunks.len() < len {
+            return true;
+        }
+        let valid = str::from_utf8(&self.chunks).is_ok();
+        self.chunks.clear();
+        valid
+    }
+
+    fn value_end(&mut self) -> bool {
+        if !self.in_string {
+            return true;
+        }
+        self.in_string = false;
+        str::from_utf8(&self.chunks).is_ok()
+    }
+}
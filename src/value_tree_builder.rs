@@ -1,7 +1,10 @@
 use crate::common::*;
+use crate::datetime::DateTimeVer30;
+use crate::schema::{Schema, ValidationError};
 use crate::tokenizer::*;
 use crate::Value;
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::{fmt, str};
 
 /// Items are stored on stack during tokenizing. Strings are incomplete utf8
@@ -14,7 +17,7 @@ enum Type {
     Binary(Vec<u8>),
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum ParsedStatus {
     Init,
     Error(String),
@@ -23,6 +26,15 @@ pub enum ParsedStatus {
     MethodCall(String),
 }
 
+/// Callback reporting received bytes of binary or FRPS data
+struct Progress(Box<dyn FnMut(usize, usize) + Send>);
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Progress")
+    }
+}
+
 #[derive(Debug)]
 pub struct ValueTreeBuilder {
     pub major_version: u8,
@@ -37,6 +49,17 @@ pub struct ValueTreeBuilder {
 
     // Frps streamed data
     pub data: Vec<u8>,
+
+    /// All announced FRPS data blocks were received whole
+    pub data_complete: bool,
+    // sum of announced data block lengths
+    data_len: usize,
+
+    // limit and sum of string, binary and data lengths
+    max_total_payload_bytes: usize,
+    payload_bytes: usize,
+
+    progress: Option<Progress>,
 }
 
 impl Default for ValueTreeBuilder {
@@ -54,7 +77,115 @@ impl ValueTreeBuilder {
             values: vec![],
             stack: vec![],
             data: vec![],
+            data_complete: true,
+            data_len: 0,
+            max_total_payload_bytes: usize::MAX,
+            payload_bytes: 0,
+            progress: None,
+        }
+    }
+
+    /// Limit sum of lengths of all strings, binaries and FRPS data in
+    /// message, unlimited by default. Protects from many values each fitting
+    /// into `MAX_*_LENGTH`. Kept by `reset`.
+    pub fn max_total_payload_bytes(mut self, max: usize) -> ValueTreeBuilder {
+        self.max_total_payload_bytes = max;
+        self
+    }
+
+    /// Call `progress(done, total)` whenever chunk of binary or FRPS data
+    /// arrives. For binary `total` is its length, for data it is sum of
+    /// data blocks announced so far. Kept by `reset`.
+    pub fn with_progress(
+        mut self,
+        progress: impl FnMut(usize, usize) + Send + 'static,
+    ) -> ValueTreeBuilder {
+        self.progress = Some(Progress(Box::new(progress)));
+        self
+    }
+
+    fn report_progress(&mut self, done: usize, total: usize) {
+        if let Some(Progress(progress)) = &mut self.progress {
+            progress(done, total);
+        }
+    }
+
+    // Account `len` bytes of payload, set error when limit is exceeded
+    fn add_payload(&mut self, len: usize) -> bool {
+        self.payload_bytes = self.payload_bytes.saturating_add(len);
+        if self.payload_bytes > self.max_total_payload_bytes {
+            self.what = ParsedStatus::Error("total payload size limit exceeded".to_owned());
+            return false;
+        }
+        true
+    }
+
+    /// Prepare builder for next message. Allocated memory is kept for reuse,
+    /// call `Tokenizer::reset` together with this.
+    pub fn reset(&mut self) {
+        self.major_version = 0;
+        self.minor_version = 0;
+        self.what = ParsedStatus::Init;
+        self.values.clear();
+        self.stack.clear();
+        self.data.clear();
+        self.data_complete = true;
+        self.data_len = 0;
+        self.payload_bytes = 0;
+    }
+
+    /// Total length of FRPS data announced by data block heads, known
+    /// before the data arrive
+    pub fn data_len(&self) -> usize {
+        self.data_len
+    }
+
+    /// Check decoded values against `schema`, report first mismatch. Only
+    /// call arguments and response value can be checked, fault and error
+    /// are rejected.
+    pub fn validate(&self, schema: &Schema) -> Result<(), ValidationError> {
+        match self.what {
+            ParsedStatus::MethodCall(_) | ParsedStatus::Response => schema.validate(&self.values),
+            ParsedStatus::Fault => Err(ValidationError {
+                path: String::new(),
+                msg: "fault can't be validated".to_owned(),
+            }),
+            _ => Err(ValidationError {
+                path: String::new(),
+                msg: "message not decoded".to_owned(),
+            }),
+        }
+    }
+
+    /// Fault code and message when fault was decoded. `None` for other
+    /// messages or when code doesn't fit in `i32`
+    pub fn fault_parts(&self) -> Option<(i32, &str)> {
+        if self.what != ParsedStatus::Fault {
+            return None;
+        }
+        match self.values.as_slice() {
+            [Value::Int(code), Value::Str(msg), ..] => Some((i32::try_from(*code).ok()?, msg)),
+            _ => None,
+        }
+    }
+
+    /// Detail struct following fault code and message, FRPS only extension
+    /// (see `Serializer::write_fault_with_detail`)
+    pub fn fault_detail(&self) -> Option<&Value> {
+        match self.what {
+            ParsedStatus::Fault => self.values.get(2),
+            _ => None,
+        }
+    }
+
+    /// Response value together with FRPS stream data. Data blocks can be
+    /// sent before the value or between parts of it, they are concatenated
+    /// in order of arrival. `None` when response was not decoded.
+    pub fn frps_result(mut self) -> Option<(Value, Vec<u8>)> {
+        if self.what != ParsedStatus::Response || self.values.len() != 1 {
+            return None;
         }
+        Some((self.values.remove(0), self.data))
     }
 
     fn append_to_last(last: &mut Type, v: Value) -> bool {
@@ -124,8 +255,12 @@ impl fmt::Display for ValueTreeBuilder {
 }
 
 impl Callback for ValueTreeBuilder {
-    /// Parsing always stop after this callback return.
+    /// Parsing always stop after this callback return. Error set by builder
+    /// itself is kept.
     fn error(&mut self, msg: &str) {
+        if let ParsedStatus::Error(_) = self.what {
+            return;
+        }
         self.what = ParsedStatus::Error(msg.to_owned())
     }
 
@@ -161,9 +296,12 @@ impl Callback for ValueTreeBuilder {
     }
 
     /// Stop on false, continue on true
+    ///
+    /// Fault can apppear in frps almost everywhere, even inside of value. So
+    /// values parsed before are dropped and only fault code and message are
+    /// kept in `values`. Stream `data` received before fault is kept.
     fn fault(&mut self) -> bool {
         self.what = ParsedStatus::Fault;
-        // Fault can apppear in frps almost everywhere
         self.stack.clear();
         self.values.clear();
         true
@@ -172,6 +310,17 @@ impl Callback for ValueTreeBuilder {
     /// Stop on false, continue on true
     fn stream_data(&mut self, v: &[u8]) -> bool {
         self.data.extend_from_slice(v);
+        self.data_complete = self.data.len() == self.data_len;
+        self.report_progress(self.data.len(), self.data_len);
+        true
+    }
+
+    fn stream_data_begin(&mut self, len: usize) -> bool {
+        if !self.add_payload(len) {
+            return false;
+        }
+        self.data_len += len;
+        self.data_complete = self.data.len() == self.data_len;
         true
     }
 
@@ -209,7 +358,7 @@ impl Callback for ValueTreeBuilder {
         true
     }
 
-    fn datetime(&mut self, v: i64) -> bool {
+    fn datetime(&mut self, v: DateTimeVer30) -> bool {
         if let Some(last) = self.stack.last_mut() {
             return ValueTreeBuilder::append_to_last(last, Value::DateTime(v));
         }
@@ -218,7 +367,7 @@ impl Callback for ValueTreeBuilder {
     }
 
     fn string_begin(&mut self, len: usize) -> bool {
-        if len > MAX_STR_LENGTH {
+        if len > MAX_STR_LENGTH || !self.add_payload(len) {
             return false;
         }
         let v = Vec::with_capacity(len);
@@ -243,7 +392,7 @@ impl Callback for ValueTreeBuilder {
     }
 
     fn binary_begin(&mut self, len: usize) -> bool {
-        if len > MAX_BIN_LENGTH {
+        if len > MAX_BIN_LENGTH || !self.add_payload(len) {
             return false;
         }
         let mut v: Vec<u8> = vec![];
@@ -252,18 +401,20 @@ impl Callback for ValueTreeBuilder {
         true
     }
 
-    fn binary_data(&mut self, v: &[u8], _len: usize) -> bool {
+    fn binary_data(&mut self, v: &[u8], len: usize) -> bool {
         // empty binary is valid too
         if v.is_empty() {
             return true;
         }
         if let Some(last) = self.stack.last_mut() {
-            match last {
+            let done = match last {
                 Type::Binary(val) => {
                     val.extend_from_slice(&v);
+                    val.len()
                 }
                 _ => return false,
-            }
+            };
+            self.report_progress(done, len);
             return true;
         }
         false
@@ -301,32 +452,277 @@ impl Callback for ValueTreeBuilder {
     }
 
     fn value_end(&mut self) -> bool {
-        if let Some(last) = self.stack.pop() {
-            // construct value
-            let v = match last {
-                Type::Struct((_, v)) => Value::Struct(v),
-                Type::Array(v) => Value::Array(v),
-                Type::Str(v) => {
-                    // let check utf8 charactes validity
-                    let v = String::from_utf8(v);
-                    if v.is_err() {
-                        return false; // is not valid utf8 encoded
-                    }
-                    Value::Str(v.unwrap())
+        let last = match self.stack.pop() {
+            Some(last) => last,
+            None => {
+                // every value_end pairs with begin of string, binary or
+                // container, so this is tokenizer bug
+                debug_assert!(false, "value_end with empty stack");
+                self.error("value_end with empty stack, tokenizer/builder desync");
+                return false;
+            }
+        };
+
+        // construct value
+        let v = match last {
+            Type::Struct((_, v)) => Value::Struct(v),
+            Type::Array(v) => Value::Array(v),
+            Type::Str(v) => {
+                // let check utf8 charactes validity
+                let v = String::from_utf8(v);
+                if v.is_err() {
+                    return false; // is not valid utf8 encoded
                 }
-                Type::Binary(v) => Value::Binary(v),
-            };
+                Value::Str(v.unwrap())
+            }
+            Type::Binary(v) => Value::Binary(v),
+        };
+
+        // append to top
+        if let Some(top) = self.stack.last_mut() {
+            return ValueTreeBuilder::append_to_last(top, v);
+        } else {
+            // when stack is empty we reach result value
+            // it can be struct, array or single value
+            self.values.push(v);
+        }
+        true
+    }
+}
 
-            // append to top
-            if let Some(top) = self.stack.last_mut() {
-                return ValueTreeBuilder::append_to_last(top, v);
-            } else {
-                // when stack is empty we reach result value
-                // it can be struct, array or single value
-                self.values.push(v);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Serializer, Version};
+    use std::sync::{Arc, Mutex};
+
+    // builder may be moved to other thread, e.g. by async runtime
+    const _: fn() = || {
+        fn assert_send<T: Send>() {}
+        assert_send::<ValueTreeBuilder>();
+    };
+
+    #[test]
+    fn reset_between_messages() {
+        let mut tokenizer = Tokenizer::new_frpc();
+        let mut tree = ValueTreeBuilder::new();
+
+        // m(1, "a")
+        let data = hex::decode("ca11030068016d08022001").unwrap();
+        assert_eq!(tokenizer.parse(&data, &mut tree), Ok((true, data.len())));
+        assert_eq!(tokenizer.parse(b"a", &mut tree), Ok((false, 1)));
+        assert_eq!(tree.to_string(), "m(1, \"a\")");
+
+        tokenizer.reset();
+        tree.reset();
+        assert_eq!(tree.what, ParsedStatus::Init);
+        assert!(tree.values.is_empty());
+
+        // response {} in version 2.1
+        let data = hex::decode("ca110201705000").unwrap();
+        assert_eq!(tokenizer.parse(&data, &mut tree), Ok((false, data.len())));
+        assert_eq!((tree.major_version, tree.minor_version), (2, 1));
+        assert_eq!(tree.to_string(), "{}");
+        assert_eq!(tree.values, vec![Value::Struct(HashMap::new())]);
+    }
+
+    #[test]
+    fn fault_interrupting_frps_stream() {
+        // response, data "ab", array of 2 items with only first one sent and
+        // fault(500, "x") instead of second item
+        let data = hex::decode("ca110201700102006162580238057839f401200178").unwrap();
+        let mut tokenizer = Tokenizer::new_frps();
+        let mut tree = ValueTreeBuilder::new();
+        assert_eq!(tokenizer.parse(&data, &mut tree), Ok((false, data.len())));
+        assert_eq!(tree.what, ParsedStatus::Fault);
+        assert_eq!(
+            tree.values,
+            vec![Value::Int(500), Value::Str("x".to_owned())]
+        );
+        assert_eq!(tree.data, b"ab");
+        assert_eq!(tree.to_string(), "fault(500, \"x\")");
+
+        // only detail struct may follow
+        assert_eq!(tokenizer.state(), TokenizerState::ExpectingOptionalArgs);
+        assert_eq!(tokenizer.parse(&[0x38], &mut tree), Err(0));
+        assert_eq!(tree.to_string(), "error(data after end)");
+    }
+
+    #[test]
+    fn fault_as_struct_member() {
+        // response, struct {a: 1, b: ...} with fault(500, "x") instead of
+        // value of member b
+        let data = hex::decode("ca1102017050020161380101627839f401200178").unwrap();
+        let mut tokenizer = Tokenizer::new_frps();
+        let mut tree = ValueTreeBuilder::new();
+        assert_eq!(tokenizer.parse(&data, &mut tree), Ok((false, data.len())));
+        assert_eq!(tokenizer.state(), TokenizerState::ExpectingOptionalArgs);
+        assert_eq!(tree.to_string(), "fault(500, \"x\")");
+        assert!(tree.stack.is_empty());
+    }
+
+    #[test]
+    fn fault_with_detail() {
+        let mut members = HashMap::new();
+        members.insert("field".to_owned(), Value::Str("name".to_owned()));
+        members.insert("retry".to_owned(), Value::Bool(false));
+        let detail = Value::Struct(members);
+
+        for version in &[Version::V21, Version::V30] {
+            let mut buffer = [0u8; 64];
+            let mut serializer = Serializer::new().with_version(*version);
+            let cnt = serializer
+                .write_fault_with_detail(&mut buffer, 400, "invalid", &detail)
+                .unwrap();
+
+            let mut tokenizer = Tokenizer::new_frps();
+            let mut tree = ValueTreeBuilder::new();
+            assert_eq!(tokenizer.parse(&buffer[..cnt], &mut tree), Ok((false, cnt)));
+            assert_eq!(tree.fault_parts(), Some((400, "invalid")));
+            assert_eq!(tree.fault_detail(), Some(&detail));
+
+            // FRPC doesn't accept detail
+            assert!(crate::parse_frpc(&buffer[..cnt]).is_err());
+
+            // detail of other type
+            let mut serializer = Serializer::new().with_version(*version);
+            assert_eq!(
+                serializer.write_fault_with_detail(&mut buffer, 400, "invalid", &Value::Int(1)),
+                Err("fault detail must be struct")
+            );
+
+            // written in small chunks
+            let mut chunked = vec![];
+            let mut serializer = Serializer::new().with_version(*version);
+            loop {
+                let mut chunk = [0u8; 3];
+                let n = serializer
+                    .write_fault_with_detail(&mut chunk, 400, "invalid", &detail)
+                    .unwrap();
+                chunked.extend_from_slice(&chunk[..n]);
+                if n < chunk.len() {
+                    break;
+                }
             }
-            return true;
+            assert_eq!(chunked, &buffer[..cnt]);
         }
-        false
+    }
+
+    #[test]
+    fn fault_in_nested_containers() {
+        // response, array [{a: [1, ...]}, ...] with fault(500, "x") inside
+        // of the innermost array, fed byte by byte
+        let data = hex::decode("ca11020170580250010161580238017839f401200178").unwrap();
+        let mut tokenizer = Tokenizer::new_frps();
+        let mut tree = ValueTreeBuilder::new();
+        for (i, byte) in data.iter().enumerate() {
+            assert_eq!(
+                tokenizer.parse(&[*byte], &mut tree),
+                Ok((i + 1 < data.len(), 1))
+            );
+        }
+        assert_eq!(tokenizer.state(), TokenizerState::ExpectingOptionalArgs);
+        assert_eq!(tree.to_string(), "fault(500, \"x\")");
+        assert!(tree.stack.is_empty());
+    }
+
+    #[test]
+    fn progress_of_binary_and_data() {
+        // response, data "abcdef" and binary "vwxyz"
+        let data = hex::decode("ca110201700106006162636465663005767778797a").unwrap();
+        let calls = Arc::new(Mutex::new(vec![]));
+        let log = calls.clone();
+        let mut tree = ValueTreeBuilder::new()
+            .with_progress(move |done, total| log.lock().unwrap().push((done, total)));
+        let mut tokenizer = Tokenizer::new_frps();
+        for chunk in data.chunks(4) {
+            assert!(tokenizer.parse(chunk, &mut tree).is_ok());
+        }
+        assert_eq!(tree.data, b"abcdef");
+        assert_eq!(tree.values, vec![Value::Binary(b"vwxyz".to_vec())]);
+
+        let calls = calls.lock().unwrap();
+        for total in &[6, 5] {
+            let done: Vec<_> = calls
+                .iter()
+                .filter(|(_, t)| t == total)
+                .map(|(d, _)| *d)
+                .collect();
+            assert!(done.len() > 1);
+            assert!(done.windows(2).all(|w| w[0] < w[1]));
+            assert_eq!(done.last(), Some(total));
+        }
+    }
+
+    #[test]
+    fn frps_response_with_data() {
+        // response with 1 KB and 2 KB data blocks followed by "ok"
+        let mut data = hex::decode("ca11020170010004").unwrap();
+        data.extend((0..1024).map(|i| i as u8));
+        data.extend(hex::decode("010008").unwrap());
+        data.extend((0..2048).map(|i| (i * 7) as u8));
+        data.extend(hex::decode("2002").unwrap());
+        data.extend(b"ok");
+
+        let mut tokenizer = Tokenizer::new_frps();
+        let mut tree = ValueTreeBuilder::new();
+        assert_eq!(tokenizer.parse(&data, &mut tree), Ok((false, data.len())));
+
+        let (value, stream) = tree.frps_result().unwrap();
+        assert_eq!(value, Value::Str("ok".to_owned()));
+        assert_eq!(stream.len(), 3072);
+        assert_eq!(&stream[..1024], &data[8..1032]);
+        assert_eq!(&stream[1024..], &data[1035..3083]);
+
+        assert!(ValueTreeBuilder::new().frps_result().is_none());
+    }
+
+    #[test]
+    fn frps_data_completeness() {
+        // response with data "abc" and "x"
+        let data = hex::decode("ca1102017001030061626301010078200178").unwrap();
+        let mut tokenizer = Tokenizer::new_frps();
+        let mut tree = ValueTreeBuilder::new();
+        assert_eq!(tokenizer.parse(&data, &mut tree), Ok((false, data.len())));
+        assert_eq!(tree.data, b"abcx");
+        assert_eq!(tree.data_len(), 4);
+        assert!(tree.data_complete);
+
+        // data block announcing 5 bytes is truncated
+        let data = hex::decode("ca11020170010500616263").unwrap();
+        let mut tokenizer = Tokenizer::new_frps();
+        let mut tree = ValueTreeBuilder::new();
+        assert_eq!(tokenizer.parse(&data, &mut tree), Ok((true, data.len())));
+        assert_eq!(tree.data, b"abc");
+        assert_eq!(tree.data_len(), 5);
+        assert!(!tree.data_complete);
+    }
+
+    #[test]
+    fn total_payload_limit() {
+        // 2000 strings of 1 KB
+        let items = (0..2000).map(|_| Value::Str("x".repeat(1024))).collect();
+        let value = Value::Array(items);
+        let mut data = vec![];
+        crate::Serializer::new()
+            .append_response(&mut data, &value)
+            .unwrap();
+
+        let mut tokenizer = Tokenizer::new_frpc();
+        let mut tree = ValueTreeBuilder::new().max_total_payload_bytes(1024 * 1024);
+        assert!(tokenizer.parse(&data, &mut tree).is_err());
+        assert_eq!(
+            tree.what,
+            ParsedStatus::Error("total payload size limit exceeded".to_owned())
+        );
+        // 1024 strings fit into limit
+        assert_eq!(tree.payload_bytes, 1025 * 1024);
+
+        // limit is kept by reset, counter is not
+        tree.reset();
+        tokenizer.reset();
+        let data = hex::decode("ca110300702003616263").unwrap();
+        assert_eq!(tokenizer.parse(&data, &mut tree), Ok((false, data.len())));
+        assert_eq!(tree.values, vec![Value::Str("abc".to_owned())]);
     }
 }
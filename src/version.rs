@@ -0,0 +1,44 @@
+use std::fmt;
+
+/// FastRPC protocol version. Only versions supported by this library can be
+/// created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    major: u8,
+    minor: u8,
+}
+
+impl Version {
+    pub const V10: Version = Version { major: 1, minor: 0 };
+    pub const V20: Version = Version { major: 2, minor: 0 };
+    pub const V21: Version = Version { major: 2, minor: 1 };
+    pub const V30: Version = Version { major: 3, minor: 0 };
+
+    /// Return `None` for unsupported version
+    pub fn new(major: u8, minor: u8) -> Option<Version> {
+        match (major, minor) {
+            (1, 0) | (2, 0) | (2, 1) | (3, 0) => Some(Version { major, minor }),
+            _ => None,
+        }
+    }
+
+    pub fn major(self) -> u8 {
+        self.major
+    }
+
+    pub fn minor(self) -> u8 {
+        self.minor
+    }
+}
+
+impl Default for Version {
+    fn default() -> Self {
+        Version::V30
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}